@@ -1,4 +1,5 @@
 use std::fs;
+use std::collections::HashMap;
 use std::io::prelude::*;
 
 use serde::{Serialize, Deserialize};
@@ -7,9 +8,82 @@ use crate::error::Error;
 
 pub static TG_IMG_SIZE_LIMIT: u32 = 4096;
 pub static TG_IMG_FILE_SIZE_LIMIT: u64 = 5 * 1024 * 1024;
+/// Limit of the size of a file that Telegram fetches from a URI,
+/// other than a photo.
+pub static TG_URI_FILE_SIZE_LIMIT: u64 = 20 * 1024 * 1024;
+/// Limit of the length of a caption, in UTF-16 code units.
+pub static TG_CAPTION_LIMIT: usize = 1024;
 pub static IMG_RESIZE_TARGET: u32 = 1024;
 pub static IMG_RESIZE_QUALITY: u32 = 92;
 
+/// Which message to pin after sending the weekly best waable.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PinTarget
+{
+    /// Do not pin anything.
+    None,
+    /// Pin the best waable itself.
+    Waable,
+    /// Pin the announcement from the bot.
+    Announcement,
+}
+
+impl Default for PinTarget
+{
+    fn default() -> Self { Self::None }
+}
+
+/// How message templates are formatted.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TextFormat
+{
+    Plain,
+    Html,
+    MarkdownV2,
+}
+
+impl Default for TextFormat
+{
+    fn default() -> Self { Self::Plain }
+}
+
+/// How a user is displayed in a mention.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MentionStyle
+{
+    /// The @username, or the full name if there is no username.
+    Username,
+    FullName,
+}
+
+impl Default for MentionStyle
+{
+    fn default() -> Self { Self::FullName }
+}
+
+fn defaultInvalidDaysTemplate() -> String
+{
+    "天数要在 1 到 ${max} 之间。".to_owned()
+}
+
+fn defaultWelcomeBatch() -> String
+{
+    "欢迎 ${users}，你们已经是键盘侠啦！快来和大家打个招呼吧~".to_owned()
+}
+
+fn defaultWelcomeSeparator() -> String
+{
+    "、".to_owned()
+}
+
+fn defaultMonthlyMembersTemplate() -> String
+{
+    "本月新加入 ${joined} 位键盘侠，离开 ${left} 位，净增 ${growth} 位。".to_owned()
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ConfigParamsGeneral
 {
@@ -22,6 +96,284 @@ pub struct ConfigParamsGeneral
     pub weekly_waable_template: String,
     pub monthly_waer_template: String,
     pub monthly_waable_template: String,
+    #[serde(default)]
+    pub pin_weekly_waable: PinTarget,
+    /// Number of seconds to wait for more new members, so that they
+    /// are welcomed in one message. 0 to welcome immediately. Members
+    /// still waiting when the bot restarts are welcomed after it
+    /// starts again.
+    #[serde(default)]
+    pub welcome_batch_sec: u64,
+    /// Welcome message for multiple new members. `${users}` is the
+    /// list of them.
+    #[serde(default = "defaultWelcomeBatch")]
+    pub welcome_batch: String,
+    /// Separator between users in `welcome_batch`.
+    #[serde(default = "defaultWelcomeSeparator")]
+    pub welcome_separator: String,
+    /// Number of seconds before welcome messages are deleted.
+    pub welcome_lifetime_sec: Option<u64>,
+    #[serde(default)]
+    pub do_farewell: bool,
+    #[serde(default)]
+    pub farewell: String,
+    #[serde(default = "defaultMonthlyMembersTemplate")]
+    pub monthly_members_template: String,
+    /// Reply to commands with an invalid number of days, e.g.
+    /// /bestpics. `${max}` is the most days allowed.
+    #[serde(default = "defaultInvalidDaysTemplate")]
+    pub invalid_days_template: String,
+    /// Format of the welcome and leaderboard templates. Plain text if
+    /// not set, so that `<` and `&` in older templates are sent as is.
+    #[serde(default)]
+    pub text_format: TextFormat,
+    #[serde(default)]
+    pub mention_style: MentionStyle,
+}
+
+fn defaultBestpicsTemplate() -> String
+{
+    "最近 ${days} 天最哇的每日键盘：\n${list}".to_owned()
+}
+
+fn defaultBestpicsItemTemplate() -> String
+{
+    "${rank}. ${title} (u/${author})，${count} 个哇 ${url}".to_owned()
+}
+
+fn defaultRedditWeeklyTemplate() -> String
+{
+    "本周 Reddit 上最赞的键盘：\n${list}".to_owned()
+}
+
+fn defaultRedditWeeklyItemTemplate() -> String
+{
+    "${rank}. ${title} ${url}".to_owned()
+}
+
+fn defaultRedditWeeklyCount() -> usize { 8 }
+
+fn defaultMonthlyBestpicsTemplate() -> String
+{
+    "本月最哇的每日键盘：\n${list}".to_owned()
+}
+
+fn defaultSourceWeight() -> f64 { 1.0 }
+
+/// A subreddit to get the daily picture from.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RedditSource
+{
+    pub subreddit: String,
+    /// Scores of the posts are multiplied by this, when ranked
+    /// together with posts from other sources.
+    #[serde(default = "defaultSourceWeight")]
+    pub weight: f64,
+    /// Caption template of the pictures from this source. Use
+    /// `daily_pic_caption` if not set.
+    pub caption: Option<String>,
+    /// The chat to send the pictures to. Use `general.group_id` if
+    /// not set.
+    pub chat_id: Option<i64>,
+}
+
+fn defaultRedditSources() -> Vec<RedditSource>
+{
+    vec![RedditSource {
+        subreddit: "MechanicalKeyboards".to_owned(),
+        weight: defaultSourceWeight(),
+        caption: None,
+        chat_id: None,
+    }]
+}
+
+/// Kind of a daily picture source other than Reddit.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentKind
+{
+    /// An RSS or Atom feed.
+    Feed,
+    /// A local folder of images.
+    Folder,
+}
+
+/// A source of the daily picture other than Reddit. Posts from these
+/// sources have no score, so their rank scores are just the weights,
+/// and the Reddit filter does not apply to them.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ContentSourceConfig
+{
+    pub name: String,
+    pub kind: ContentKind,
+    /// URL of the feed, or path to the folder.
+    pub location: String,
+    /// Rank score of every post from this source. It competes with
+    /// the rank scores of Reddit posts, which depend on `ranking`, so
+    /// it has to be chosen for the ranking in use.
+    #[serde(default = "defaultSourceWeight")]
+    pub weight: f64,
+    /// Caption template of the pictures from this source. Use
+    /// `daily_pic_caption` if not set.
+    pub caption: Option<String>,
+    /// The chat to send the pictures to. Use `general.group_id` if
+    /// not set.
+    pub chat_id: Option<i64>,
+}
+
+/// Rules to reject posts as the daily picture.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct RedditFilter
+{
+    #[serde(default)]
+    pub allow_nsfw: bool,
+    #[serde(default)]
+    pub allow_spoiler: bool,
+    /// If not empty, only keep posts with one of these flairs.
+    #[serde(default)]
+    pub include_flairs: Vec<String>,
+    #[serde(default)]
+    pub exclude_flairs: Vec<String>,
+    pub min_score: Option<i32>,
+    pub min_upvote_ratio: Option<f64>,
+    /// Usernames or full names (t2_...) of authors whose posts are
+    /// rejected. A full name still matches after a rename.
+    #[serde(default)]
+    pub blocked_authors: Vec<String>,
+}
+
+/// How to rank the candidates of the daily picture.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Ranking
+{
+    /// The raw score.
+    Score,
+    /// Score × upvote ratio, which favors less controversial posts.
+    ScoreRatio,
+    /// Score + `comment_weight` × number of comments.
+    Comments,
+    /// Score per hour since posting, which favors newer posts.
+    ScorePerHour,
+}
+
+impl Default for Ranking
+{
+    fn default() -> Self { Self::Score }
+}
+
+fn defaultCommentWeight() -> f64 { 1.0 }
+
+fn defaultVoteCandidates() -> usize { 4 }
+
+fn defaultVoteDurationSec() -> u64 { 3 * 3600 }
+
+fn defaultVoteQuestion() -> String
+{
+    "今天的每日键盘选哪个？".to_owned()
+}
+
+fn defaultVoteStatsTemplate() -> String
+{
+    "一共投了 ${polls} 次票，有 ${agreed} 次选了 Reddit 上分最高的，\
+     选中的平均是 Reddit 上第 ${rank} 名。".to_owned()
+}
+
+/// Let the group vote on the daily picture, instead of sending the
+/// best one on Reddit.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ConfigParamsVote
+{
+    /// Number of candidates in the vote, from 2 to 10.
+    #[serde(default = "defaultVoteCandidates")]
+    pub candidates: usize,
+    /// The poll is closed after this many seconds. The running bot
+    /// closes it, not the `send-reddit-best` command.
+    #[serde(default = "defaultVoteDurationSec")]
+    pub duration_sec: u64,
+    #[serde(default = "defaultVoteQuestion")]
+    pub question: String,
+    /// Pin the winner in the album, instead of sending it again.
+    #[serde(default)]
+    pub pin_winner: bool,
+    /// Reply to the /votestats command, with `${polls}`, `${agreed}`
+    /// and `${rank}`.
+    #[serde(default = "defaultVoteStatsTemplate")]
+    pub stats_template: String,
+}
+
+/// A rule to alert about new posts on Reddit. A post matches if it
+/// meets all the conditions given.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WatchRule
+{
+    /// Name of the rule, used in alerts and by /subscribe.
+    pub name: String,
+    pub subreddit: String,
+    /// Regex that the title must match.
+    pub title: Option<String>,
+    /// The flair must be this, case insensitively.
+    pub flair: Option<String>,
+}
+
+fn defaultWatchIntervalSec() -> u64 { 300 }
+
+fn defaultWatchTemplate() -> String
+{
+    "【${rule}】${title} (r/${subreddit}) ${url}".to_owned()
+}
+
+fn defaultWatchSubscribedTemplate() -> String
+{
+    "已订阅：${rules}".to_owned()
+}
+
+fn defaultWatchUnsubscribedTemplate() -> String
+{
+    "已取消订阅：${rules}".to_owned()
+}
+
+/// Alert about new posts on Reddit that match the rules. Users
+/// subscribe to the alerts with /subscribe in a private chat with the
+/// bot.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ConfigParamsWatch
+{
+    /// Number of seconds between checks for new posts.
+    #[serde(default = "defaultWatchIntervalSec")]
+    pub interval_sec: u64,
+    /// Also send the alerts to this chat.
+    pub chat_id: Option<i64>,
+    /// Template of the alert, with `${rule}`, `${title}`, `${author}`,
+    /// `${flair}`, `${subreddit}` and `${url}`.
+    #[serde(default = "defaultWatchTemplate")]
+    pub template: String,
+    /// Reply to /subscribe, with `${rules}`.
+    #[serde(default = "defaultWatchSubscribedTemplate")]
+    pub subscribed_template: String,
+    /// Reply to /unsubscribe, with `${rules}`.
+    #[serde(default = "defaultWatchUnsubscribedTemplate")]
+    pub unsubscribed_template: String,
+    /// A post is alerted about once, to the rules and subscribers at
+    /// the time. Rules added later do not alert about posts seen
+    /// before.
+    #[serde(default)]
+    pub rules: Vec<WatchRule>,
+}
+
+fn defaultRedditAuthUrl() -> String
+{
+    "https://www.reddit.com".to_owned()
+}
+
+fn defaultRedditApiUrl() -> String
+{
+    "https://oauth.reddit.com".to_owned()
+}
+
+fn defaultTokenCache() -> String
+{
+    "reddit-token.json".to_owned()
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -29,7 +381,187 @@ pub struct ConfigParamsReddit
 {
     pub client_id: String,
     pub client_secret: String,
+    /// Caption template of the daily picture, in `general.text_format`.
+    /// The variables are `${url}`, `${title}`, `${author}`,
+    /// `${score}`, `${count_comments}`, `${subreddit}`, `${flair}` and
+    /// `${time}`.
     pub daily_pic_caption: String,
+    /// Template of the reply to the /bestpics command. `${list}` is
+    /// replaced by the list of pictures, one line per picture.
+    #[serde(default = "defaultBestpicsTemplate")]
+    pub bestpics_template: String,
+    /// Template of one line in the list of best daily pictures.
+    #[serde(default = "defaultBestpicsItemTemplate")]
+    pub bestpics_item_template: String,
+    #[serde(default = "defaultMonthlyBestpicsTemplate")]
+    pub monthly_bestpics_template: String,
+    /// Caption template of the weekly album. `${list}` is replaced
+    /// by the list of posts, one line per post.
+    #[serde(default = "defaultRedditWeeklyTemplate")]
+    pub weekly_template: String,
+    /// Template of one line in the caption of the weekly album.
+    /// The variables are `${rank}`, `${title}`, `${author}`,
+    /// `${score}`, `${subreddit}` and `${url}`.
+    #[serde(default = "defaultRedditWeeklyItemTemplate")]
+    pub weekly_item_template: String,
+    /// Number of pictures in the weekly album, from 5 to 10. If fewer
+    /// than 5 pictures are found or can be sent, the album is not sent.
+    #[serde(default = "defaultRedditWeeklyCount")]
+    pub weekly_count: usize,
+    pub vote: Option<ConfigParamsVote>,
+    pub watch: Option<ConfigParamsWatch>,
+    #[serde(default = "defaultRedditSources")]
+    pub sources: Vec<RedditSource>,
+    /// Where to cache the Reddit access token between runs.
+    #[serde(default = "defaultTokenCache")]
+    pub token_cache: String,
+    #[serde(default)]
+    pub filter: RedditFilter,
+    #[serde(default)]
+    pub ranking: Ranking,
+    /// How much a comment is worth in the `comments` ranking.
+    #[serde(default = "defaultCommentWeight")]
+    pub comment_weight: f64,
+    /// Base URL of the Reddit authorization API.
+    #[serde(default = "defaultRedditAuthUrl")]
+    pub auth_url: String,
+    /// Base URL of the Reddit OAuth API.
+    #[serde(default = "defaultRedditApiUrl")]
+    pub api_url: String,
+}
+
+fn defaultFlaggedTemplate() -> String
+{
+    "最近 ${days} 天被限制的哇：\n${list}".to_owned()
+}
+
+/// Limits on how often a user can wa. Wa-s over the limits are
+/// flagged, and are not counted.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ConfigParamsWaLimit
+{
+    /// Maximal number of wa-s from one user in a chat in an hour.
+    pub max_per_hour: Option<u32>,
+    /// Minimal number of seconds between two wa-s from one user in a
+    /// chat, including the flagged ones.
+    pub min_gap_sec: Option<i64>,
+    /// Template of the reply to the /flagged command.
+    #[serde(default = "defaultFlaggedTemplate")]
+    pub flagged_template: String,
+}
+
+impl Default for ConfigParamsWaLimit
+{
+    fn default() -> Self
+    {
+        Self {
+            max_per_hour: None,
+            min_gap_sec: None,
+            flagged_template: defaultFlaggedTemplate(),
+        }
+    }
+}
+
+fn defaultHallOfFameTemplate() -> String
+{
+    "${author} 的这条消息收到了 ${count} 个哇！\n${link}".to_owned()
+}
+
+/// Messages with enough wa-s are forwarded to a hall of fame channel.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ConfigParamsHallOfFame
+{
+    pub channel_id: i64,
+    /// Number of wa-s for a message to get into the hall of fame.
+    pub threshold: u32,
+    /// Template of the message that credits the author, sent after
+    /// the forwarded message.
+    #[serde(default = "defaultHallOfFameTemplate")]
+    pub template: String,
+}
+
+/// A multiple-choice question for new members.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CaptchaQuestion
+{
+    pub question: String,
+    pub choices: Vec<String>,
+    /// Index of the correct choice in `choices`.
+    pub answer: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptchaKind
+{
+    /// A random question from `ConfigParamsCaptcha::questions`.
+    Quiz,
+    /// A simple addition.
+    Arithmetic,
+}
+
+fn defaultCaptchaTimeout() -> u64 { 120 }
+
+fn defaultCaptchaPrompt() -> String
+{
+    "${user}，请在 ${timeout} 秒内回答：${question}".to_owned()
+}
+
+fn defaultCaptchaNotYours() -> String
+{
+    "这不是你的问题哦".to_owned()
+}
+
+fn defaultCaptchaWrong() -> String
+{
+    "回答错误".to_owned()
+}
+
+fn defaultCaptchaRight() -> String
+{
+    "回答正确".to_owned()
+}
+
+/// New members are restricted until they answer a question.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ConfigParamsCaptcha
+{
+    pub kind: CaptchaKind,
+    /// Number of seconds to answer before getting kicked.
+    #[serde(default = "defaultCaptchaTimeout")]
+    pub timeout_sec: u64,
+    #[serde(default = "defaultCaptchaPrompt")]
+    pub prompt: String,
+    /// Shown to a user who answers the question for someone else.
+    #[serde(default = "defaultCaptchaNotYours")]
+    pub not_yours: String,
+    /// Shown to a user who answers wrong, before getting kicked.
+    #[serde(default = "defaultCaptchaWrong")]
+    pub wrong_answer: String,
+    #[serde(default = "defaultCaptchaRight")]
+    pub right_answer: String,
+    #[serde(default)]
+    pub questions: Vec<CaptchaQuestion>,
+}
+
+/// Translations of the message templates.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ConfigParamsI18n
+{
+    /// Language of group-wide messages in chats not in
+    /// `chat_languages`.
+    pub default_language: Option<String>,
+    /// Language of group-wide messages, keyed by chat ID.
+    #[serde(default)]
+    pub chat_languages: HashMap<String, String>,
+    /// Languages to try if there is no translation in the wanted
+    /// language, in order.
+    #[serde(default)]
+    pub fallback: Vec<String>,
+    /// Message templates keyed by language, then by the name of the
+    /// template in the config, e.g. `welcome`.
+    #[serde(default)]
+    pub catalogs: HashMap<String, HashMap<String, String>>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -37,6 +569,14 @@ pub struct ConfigParams
 {
     pub general: ConfigParamsGeneral,
     pub reddit: ConfigParamsReddit,
+    #[serde(default)]
+    pub wa_limit: ConfigParamsWaLimit,
+    pub hall_of_fame: Option<ConfigParamsHallOfFame>,
+    pub captcha: Option<ConfigParamsCaptcha>,
+    #[serde(default)]
+    pub i18n: ConfigParamsI18n,
+    #[serde(default)]
+    pub content_sources: Vec<ContentSourceConfig>,
 }
 
 impl ConfigParams
@@ -0,0 +1,301 @@
+use std::time;
+
+use rand::prelude::*;
+use log::{info,debug};
+use log::error as log_error;
+use tokio;
+use telegram_bot as bot;
+use telegram_bot::types::requests::{SendMessage, CanAnswerCallbackQuery};
+use chrono;
+
+use crate::error::Error;
+use crate::bot_config;
+use crate::i18n;
+use crate::telegram;
+use crate::chat_db;
+use crate::keybot;
+
+/// Prefix of the callback data of the answer buttons.
+const CALLBACK_PREFIX: &str = "captcha";
+
+/// A question with choices, and the index of the correct choice.
+struct Question
+{
+    question: String,
+    choices: Vec<String>,
+    answer: usize,
+}
+
+fn makeArithmeticQuestion() -> Question
+{
+    let mut rng = thread_rng();
+    let a: i32 = rng.gen_range(1, 20);
+    let b: i32 = rng.gen_range(1, 20);
+    let sum = a + b;
+    let mut values = vec![sum];
+    while values.len() < 4
+    {
+        let v = sum + rng.gen_range(-5, 6);
+        if v > 0 && !values.contains(&v)
+        {
+            values.push(v);
+        }
+    }
+    values.shuffle(&mut rng);
+    Question {
+        question: format!("{} + {} = ?", a, b),
+        answer: values.iter().position(|v| *v == sum).unwrap(),
+        choices: values.iter().map(|v| v.to_string()).collect(),
+    }
+}
+
+fn makeQuestion(config: &bot_config::ConfigParamsCaptcha) -> Result<Question, Error>
+{
+    match config.kind
+    {
+        bot_config::CaptchaKind::Arithmetic => Ok(makeArithmeticQuestion()),
+        bot_config::CaptchaKind::Quiz =>
+        {
+            let q = config.questions.choose(&mut thread_rng()).ok_or_else(
+                || error!(RuntimeError, "No captcha question configured"))?;
+            if q.answer >= q.choices.len()
+            {
+                return Err(error!(RuntimeError, format!(
+                    "Invalid answer in captcha question: {}", q.question)));
+            }
+            Ok(Question {
+                question: q.question.clone(),
+                choices: q.choices.clone(),
+                answer: q.answer,
+            })
+        },
+    }
+}
+
+fn callbackData(chat_id: i64, user_id: i64, choice: usize) -> String
+{
+    format!("{}:{}:{}:{}", CALLBACK_PREFIX, chat_id, user_id, choice)
+}
+
+/// Parse the callback data of an answer button into (chat ID, user
+/// ID, choice).
+fn parseCallbackData(data: &str) -> Option<(i64, i64, i64)>
+{
+    let mut parts = data.split(':');
+    if parts.next()? != CALLBACK_PREFIX
+    {
+        return None;
+    }
+    let chat_id = parts.next()?.parse().ok()?;
+    let user_id = parts.next()?.parse().ok()?;
+    let choice = parts.next()?.parse().ok()?;
+    Some((chat_id, user_id, choice))
+}
+
+#[test]
+fn testCallbackData()
+{
+    assert_eq!(parseCallbackData(&callbackData(-1001234, 5678, 2)),
+               Some((-1001234, 5678, 2)));
+    assert_eq!(parseCallbackData("something:1:2:3"), None);
+}
+
+/// Ask `user` the question, and restrict the user in the chat until
+/// it is answered. If anything fails, the user is left unrestricted.
+pub async fn challenge(api: &bot::Api, config: &bot_config::ConfigParams,
+                       captcha_config: &bot_config::ConfigParamsCaptcha,
+                       chat_id: i64, user: &bot::User) -> Result<(), Error>
+{
+    let user_id = i64::from(user.id);
+    info!("Challenging {} in chat {}...", telegram::getUsername(user), chat_id);
+    let question = makeQuestion(captcha_config)?;
+    let mut keyboard = bot::types::InlineKeyboardMarkup::new();
+    for (i, choice) in question.choices.iter().enumerate()
+    {
+        keyboard.add_row(vec![bot::types::InlineKeyboardButton::callback(
+            choice.as_str(), callbackData(chat_id, user_id, i))]);
+    }
+    let text = i18n::template(config, "captcha_prompt",
+                              user.language_code.as_deref(), Some(chat_id))?
+        .apply("user", telegram::mention(user, config.general.mention_style,
+                                         config.general.text_format))
+        .apply("timeout", captcha_config.timeout_sec)
+        .apply("question", telegram::escape(&question.question,
+                                            config.general.text_format))
+        .result();
+    let mut req = SendMessage::new(bot::types::ChatId::new(chat_id), text);
+    req.reply_markup(keyboard);
+    if let Some(mode) = telegram::getParseMode(config.general.text_format)
+    {
+        req.parse_mode(mode);
+    }
+    let post = api.send(req).await.map_err(
+        |_| error!(RuntimeError, "Failed to send captcha"))?;
+    let msg_id = telegram::getPostId(&post);
+
+    let token = &config.general.token;
+    let deadline = chrono::Utc::now() +
+        chrono::Duration::seconds(captcha_config.timeout_sec as i64);
+    let restricted = telegram::restrictMember(token, chat_id, user_id, false)
+        .await;
+    let added = match restricted
+    {
+        Ok(()) => chat_db::addCaptcha(chat_db::CaptchaEntry {
+            chat_id: chat_id,
+            user_id: user_id,
+            msg_id: i64::from(msg_id),
+            answer: question.answer as i64,
+            deadline: deadline,
+        }),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = added
+    {
+        // Without a pending captcha nothing would lift the
+        // restriction or kick the user.
+        if let Err(e) = telegram::restrictMember(token, chat_id, user_id, true)
+            .await
+        {
+            log_error!("{}", e);
+        }
+        if let Err(e) = telegram::deleteMessage(
+            api, bot::types::ChatId::new(chat_id), msg_id).await
+        {
+            log_error!("{}", e);
+        }
+        return Err(e);
+    }
+    expireLater(api.clone(), chat_id, user_id, i64::from(msg_id), deadline);
+    Ok(())
+}
+
+/// Answer the callback query with the template `key`, in the language
+/// of the user. The answer is only a notice, so failures, e.g. of an
+/// expired query, are logged and do not stop the captcha.
+async fn answerQuery(api: &bot::Api, config: &bot_config::ConfigParams,
+                     query: &bot::types::CallbackQuery, key: &str, chat_id: i64)
+{
+    let text = match i18n::lookup(config, key, query.from.language_code.as_deref(),
+                                  Some(chat_id))
+    {
+        Ok(text) => text,
+        Err(e) => { log_error!("{}", e); return; },
+    };
+    if api.send(query.answer(text)).await.is_err()
+    {
+        log_error!("Failed to answer callback query from {}.",
+                   telegram::getUsername(&query.from));
+    }
+}
+
+/// Kick the user out, remove the question, and forget the captcha.
+async fn fail(api: &bot::Api, captcha: &chat_db::CaptchaEntry) -> Result<(), Error>
+{
+    info!("User {} failed the captcha in chat {}.", captcha.user_id,
+          captcha.chat_id);
+    let chat_id = bot::types::ChatId::new(captcha.chat_id);
+    if let Err(e) = telegram::deleteMessage(
+        api, chat_id, bot::types::MessageId::new(captcha.msg_id)).await
+    {
+        log_error!("{}", e);
+    }
+    telegram::kickMember(api, chat_id, bot::types::UserId::new(captcha.user_id))
+        .await?;
+    // Only forget the captcha after the kick, so that a failed kick
+    // is tried again after a restart.
+    chat_db::removeCaptcha(captcha.chat_id, captcha.user_id, captcha.msg_id)?;
+    Ok(())
+}
+
+/// Kick the user at `deadline` if the question in message `msg_id` is
+/// still not answered.
+fn expireLater(api: bot::Api, chat_id: i64, user_id: i64, msg_id: i64,
+               deadline: chrono::DateTime<chrono::Utc>)
+{
+    tokio::spawn(async move {
+        let wait = (deadline - chrono::Utc::now()).to_std()
+            .unwrap_or(time::Duration::from_secs(0));
+        tokio::time::delay_for(wait).await;
+        match chat_db::getCaptcha(chat_id, user_id)
+        {
+            // If the user left and rejoined, the newer captcha has its
+            // own timer.
+            Ok(Some(captcha)) if captcha.msg_id == msg_id =>
+            {
+                if let Err(e) = fail(&api, &captcha).await
+                {
+                    log_error!("{}", e);
+                }
+            },
+            Ok(_) => (),
+            Err(e) => log_error!("{}", e),
+        }
+    });
+}
+
+/// Restart the timers of the pending captchas, after the bot is
+/// restarted.
+pub fn resumePending(api: &bot::Api) -> Result<(), Error>
+{
+    let captchas = chat_db::pendingCaptchas()?;
+    debug!("Resuming {} pending captchas.", captchas.len());
+    for captcha in captchas
+    {
+        expireLater(api.clone(), captcha.chat_id, captcha.user_id,
+                    captcha.msg_id, captcha.deadline);
+    }
+    Ok(())
+}
+
+/// Handle a click on an answer button. Return false if the callback
+/// query is not for a captcha.
+pub async fn onCallbackQuery(api: &bot::Api, config: &bot_config::ConfigParams,
+                             query: &bot::types::CallbackQuery)
+                             -> Result<bool, Error>
+{
+    let (chat_id, user_id, choice) = match query.data.as_ref()
+        .and_then(|data| parseCallbackData(data))
+    {
+        Some(parsed) => parsed,
+        None => return Ok(false),
+    };
+
+    if i64::from(query.from.id) != user_id
+    {
+        answerQuery(api, config, query, "captcha_not_yours", chat_id).await;
+        return Ok(true);
+    }
+    let captcha = match chat_db::getCaptcha(chat_id, user_id)?
+    {
+        Some(captcha) => captcha,
+        None => return Ok(true),
+    };
+
+    if choice != captcha.answer
+    {
+        answerQuery(api, config, query, "captcha_wrong_answer", chat_id).await;
+        return fail(api, &captcha).await.map(|_| true);
+    }
+
+    info!("{} passed the captcha in chat {}.",
+          telegram::getUsername(&query.from), chat_id);
+    answerQuery(api, config, query, "captcha_right_answer", chat_id).await;
+    telegram::restrictMember(&config.general.token, chat_id, user_id, true).await?;
+    // Only forget the captcha after the restriction is lifted, so
+    // that the user is not left muted if lifting fails.
+    if !chat_db::removeCaptcha(chat_id, user_id, captcha.msg_id)?
+    {
+        return Ok(true);
+    }
+    if let Err(e) = telegram::deleteMessage(
+        api, bot::types::ChatId::new(chat_id),
+        bot::types::MessageId::new(captcha.msg_id)).await
+    {
+        log_error!("{}", e);
+    }
+    if config.general.do_welcome
+    {
+        keybot::welcomeInBatch(api, config, &[query.from.clone()], chat_id).await?;
+    }
+    Ok(true)
+}
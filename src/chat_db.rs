@@ -1,4 +1,5 @@
 use chrono;
+use chrono::TimeZone;
 use rusqlite;
 
 use crate::error::Error;
@@ -10,6 +11,8 @@ pub static DB_FILENAME: &str = "chat.db";
 /// A wa message.
 pub struct WaEntry
 {
+    /// The chat of the wa and the message it is for.
+    pub chat_id: i64,
     /// The messages ID that the wa is for.
     pub wa_to: i64,
     /// The ID of the wa message.
@@ -20,25 +23,359 @@ pub struct WaEntry
     pub time: DateTime,
 }
 
+/// A daily picture from Reddit sent by the bot.
+pub struct DailyPicEntry
+{
+    pub chat_id: i64,
+    /// The ID of the message of the picture.
+    pub msg_id: i64,
+    /// The full name of the Reddit post.
+    pub post_id: String,
+    /// The title of the Reddit post.
+    pub title: String,
+    /// The author of the Reddit post.
+    pub author: String,
+    /// The short URL to the Reddit post.
+    pub url: String,
+    /// The time the picture was sent.
+    pub time: DateTime,
+}
+
+/// The message pinned by the bot in a chat.
+pub struct PinState
+{
+    pub chat_id: i64,
+    /// The message pinned by the bot.
+    pub bot_pin: i64,
+    /// The message that was pinned before the bot pinned anything.
+    pub original_pin: Option<i64>,
+}
+
+/// A question waiting for the answer from a new member.
+pub struct CaptchaEntry
+{
+    pub chat_id: i64,
+    pub user_id: i64,
+    /// The ID of the message with the question.
+    pub msg_id: i64,
+    /// Index of the correct choice.
+    pub answer: i64,
+    /// The user is kicked if not answered before this time.
+    pub deadline: DateTime,
+}
+
+/// A member joining or leaving a chat.
+pub struct MemberEntry
+{
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub first_name: String,
+    pub last_name: Option<String>,
+    pub username: Option<String>,
+    /// The time of joining or leaving.
+    pub time: DateTime,
+    /// The user ID of who added the member, if not the member
+    /// themselves.
+    pub added_by: Option<i64>,
+}
+
+/// A poll to choose the daily picture.
+pub struct PollEntry
+{
+    pub chat_id: i64,
+    /// The ID of the message with the poll.
+    pub msg_id: i64,
+    pub poll_id: String,
+    /// The poll is closed at this time.
+    pub close_time: DateTime,
+}
+
+/// A candidate in a poll of the daily picture.
+pub struct PollOptionEntry
+{
+    /// Index of the option in the poll.
+    pub index: i64,
+    /// The ID of the message of the candidate in the album.
+    pub album_msg_id: i64,
+    pub post_id: String,
+    pub title: String,
+    pub author: String,
+    pub url: String,
+    /// The caption to use if the candidate wins.
+    pub caption: String,
+    /// Score of the post where it is from, e.g. on Reddit.
+    pub score: i64,
+    /// Number of votes, known after the poll is closed.
+    pub votes: Option<i64>,
+    /// Canonical URL of the media in the post, to remember it as sent
+    /// if it wins.
+    pub media_url: String,
+}
+
 fn connect() -> Result<rusqlite::Connection, Error>
 {
     rusqlite::Connection::open(DB_FILENAME).map_err(
         |_| error!(DBError, "Failed to open/create chat database"))
 }
 
-pub fn initialize() -> Result<(), Error>
+/// Tables that were keyed by message IDs alone, before the daily
+/// pictures and wa-s could be in several chats, with their old columns.
+const TABLES_WITHOUT_CHAT: [(&str, &str); 4] = [
+    ("was", "id, wa_to, waer, time"),
+    ("daily_pics", "msg_id, post_id, title, author, url, time"),
+    ("flagged_was", "id, wa_to, waer, time, reason"),
+    ("hall_of_fame", "msg_id, author, forwarded_id, time"),
+];
+
+/// Return the names of the columns in `table`, or nothing if there is
+/// no such table.
+fn columnNames(conn: &rusqlite::Connection, table: &str)
+               -> Result<Vec<String>, Error>
 {
-    let conn = connect()?;
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({});", table))
+        .map_err(|_| error!(DBError, "Failed to prepare table info query"))?;
+    let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| row.get(1))
+        .map_err(|_| error!(DBError, "Failed to get table info"))?;
+    rows.collect::<Result<Vec<String>, _>>().map_err(
+        |_| error!(DBError, "Failed to read table info"))
+}
+
+/// Move the old tables without chat IDs out of the way, so that they
+/// are created again with chat IDs. Return the tables moved. Nothing
+/// is moved if there is no `legacy_chat_id` to copy the rows to.
+fn moveTablesWithoutChat(conn: &rusqlite::Connection, legacy_chat_id: Option<i64>)
+                         -> Result<Vec<(&'static str, &'static str)>, Error>
+{
+    let mut old_tables = Vec::new();
+    for (table, columns) in TABLES_WITHOUT_CHAT.iter()
+    {
+        let names = columnNames(conn, table)?;
+        if !names.is_empty() && !names.iter().any(|n| n == "chat_id")
+        {
+            old_tables.push((*table, *columns));
+        }
+    }
+    if !old_tables.is_empty() && legacy_chat_id.is_none()
+    {
+        return Err(error!(DBError, "Old tables need general.group_id to migrate"));
+    }
+
+    for (table, _) in &old_tables
+    {
+        conn.execute(&format!("ALTER TABLE {0} RENAME TO {0}_old;", table),
+                     rusqlite::NO_PARAMS)
+            .map_err(|_| error!(DBError, format!("Failed to move table '{}'",
+                                                 table)))?;
+    }
+    Ok(old_tables)
+}
+
+/// Copy the rows in the moved tables into the new ones. The old rows
+/// are all from `legacy_chat_id`, the only chat there was.
+fn copyTablesWithoutChat(conn: &rusqlite::Connection,
+                         moved: &[(&str, &str)], legacy_chat_id: i64)
+                         -> Result<(), Error>
+{
+    for (table, columns) in moved
+    {
+        conn.execute(
+            &format!("INSERT INTO {0} (chat_id, {1}) SELECT ?1, {1} FROM {0}_old;",
+                     table, columns),
+            rusqlite::params![legacy_chat_id])
+            .map_err(|_| error!(DBError, format!("Failed to copy table '{}'",
+                                                 table)))?;
+        conn.execute(&format!("DROP TABLE {}_old;", table), rusqlite::NO_PARAMS)
+            .map_err(|_| error!(DBError, format!("Failed to drop table '{}_old'",
+                                                 table)))?;
+    }
+    Ok(())
+}
+
+/// Create the tables. Rows in old tables without chat IDs are moved to
+/// `legacy_chat_id`.
+pub fn initialize(legacy_chat_id: Option<i64>) -> Result<(), Error>
+{
+    let mut conn = connect()?;
+    // Migrate in one transaction, so that a failed step does not leave
+    // moved tables behind for the next start to trip over.
+    let conn = conn.transaction().map_err(
+        |_| error!(DBError, "Failed to start transaction"))?;
+    let moved = moveTablesWithoutChat(&conn, legacy_chat_id)?;
     conn.execute(
-        "CREATE TABLE was (
-                  id              INTEGER PRIMARY KEY,
+        "CREATE TABLE IF NOT EXISTS was (
+                  chat_id         INTEGER,
+                  id              INTEGER,
                   wa_to           INTEGER,
                   waer            INTEGER,
-                  time            INTEGER
+                  time            INTEGER,
+                  PRIMARY KEY (chat_id, id)
                   );",
         rusqlite::NO_PARAMS,
     ).map_err(|_| error!(DBError, "Failed to create table 'was'"))?;
-    Ok(())
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS daily_pics (
+                  chat_id         INTEGER,
+                  msg_id          INTEGER,
+                  post_id         TEXT,
+                  title           TEXT,
+                  author          TEXT,
+                  url             TEXT,
+                  time            INTEGER,
+                  PRIMARY KEY (chat_id, msg_id)
+                  );",
+        rusqlite::NO_PARAMS,
+    ).map_err(|_| error!(DBError, "Failed to create table 'daily_pics'"))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS flagged_was (
+                  chat_id         INTEGER,
+                  id              INTEGER,
+                  wa_to           INTEGER,
+                  waer            INTEGER,
+                  time            INTEGER,
+                  reason          TEXT,
+                  PRIMARY KEY (chat_id, id)
+                  );",
+        rusqlite::NO_PARAMS,
+    ).map_err(|_| error!(DBError, "Failed to create table 'flagged_was'"))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS hall_of_fame (
+                  chat_id         INTEGER,
+                  msg_id          INTEGER,
+                  author          INTEGER,
+                  forwarded_id    INTEGER,
+                  time            INTEGER,
+                  PRIMARY KEY (chat_id, msg_id)
+                  );",
+        rusqlite::NO_PARAMS,
+    ).map_err(|_| error!(DBError, "Failed to create table 'hall_of_fame'"))?;
+    if let Some(chat_id) = legacy_chat_id
+    {
+        copyTablesWithoutChat(&conn, &moved, chat_id)?;
+    }
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS pins (
+                  chat_id         INTEGER PRIMARY KEY,
+                  bot_pin         INTEGER,
+                  original_pin    INTEGER
+                  );",
+        rusqlite::NO_PARAMS,
+    ).map_err(|_| error!(DBError, "Failed to create table 'pins'"))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS captchas (
+                  chat_id         INTEGER,
+                  user_id         INTEGER,
+                  msg_id          INTEGER,
+                  answer          INTEGER,
+                  deadline        INTEGER,
+                  PRIMARY KEY (chat_id, user_id)
+                  );",
+        rusqlite::NO_PARAMS,
+    ).map_err(|_| error!(DBError, "Failed to create table 'captchas'"))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS members (
+                  id              INTEGER PRIMARY KEY,
+                  chat_id         INTEGER,
+                  user_id         INTEGER,
+                  first_name      TEXT,
+                  last_name       TEXT,
+                  username        TEXT,
+                  join_time       INTEGER,
+                  leave_time      INTEGER,
+                  added_by        INTEGER
+                  );",
+        rusqlite::NO_PARAMS,
+    ).map_err(|_| error!(DBError, "Failed to create table 'members'"))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS deletions (
+                  chat_id         INTEGER,
+                  msg_id          INTEGER,
+                  due             INTEGER,
+                  PRIMARY KEY (chat_id, msg_id)
+                  );",
+        rusqlite::NO_PARAMS,
+    ).map_err(|_| error!(DBError, "Failed to create table 'deletions'"))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS pending_welcomes (
+                  chat_id         INTEGER,
+                  user_id         INTEGER,
+                  user            TEXT,
+                  due             INTEGER,
+                  PRIMARY KEY (chat_id, user_id)
+                  );",
+        rusqlite::NO_PARAMS,
+    ).map_err(|_| error!(DBError, "Failed to create table 'pending_welcomes'"))?;
+    // Sent posts from all the daily picture sources. The table keeps
+    // its old name, from when Reddit was the only source.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS posted_reddit (
+                  chat_id         INTEGER,
+                  fullname        TEXT,
+                  media_url       TEXT,
+                  time            INTEGER,
+                  PRIMARY KEY (chat_id, fullname)
+                  );",
+        rusqlite::NO_PARAMS,
+    ).map_err(|_| error!(DBError, "Failed to create table 'posted_reddit'"))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS polls (
+                  chat_id         INTEGER,
+                  msg_id          INTEGER,
+                  poll_id         TEXT,
+                  close_time      INTEGER,
+                  closed          INTEGER,
+                  PRIMARY KEY (chat_id, msg_id)
+                  );",
+        rusqlite::NO_PARAMS,
+    ).map_err(|_| error!(DBError, "Failed to create table 'polls'"))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS poll_options (
+                  chat_id         INTEGER,
+                  poll_msg_id     INTEGER,
+                  idx             INTEGER,
+                  album_msg_id    INTEGER,
+                  post_id         TEXT,
+                  title           TEXT,
+                  author          TEXT,
+                  url             TEXT,
+                  caption         TEXT,
+                  score           INTEGER,
+                  votes           INTEGER,
+                  media_url       TEXT,
+                  PRIMARY KEY (chat_id, poll_msg_id, idx)
+                  );",
+        rusqlite::NO_PARAMS,
+    ).map_err(|_| error!(DBError, "Failed to create table 'poll_options'"))?;
+    if !columnNames(&conn, "poll_options")?.iter().any(|c| c == "media_url")
+    {
+        conn.execute("ALTER TABLE poll_options ADD COLUMN media_url TEXT;",
+                     rusqlite::NO_PARAMS)
+            .map_err(|_| error!(DBError, "Failed to add column 'media_url'"))?;
+    }
+    // Kept apart from `pins`, so that pinning the winner of a vote
+    // does not replace the pin that `restorePin()` restores.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS vote_pins (
+                  chat_id         INTEGER PRIMARY KEY,
+                  msg_id          INTEGER
+                  );",
+        rusqlite::NO_PARAMS,
+    ).map_err(|_| error!(DBError, "Failed to create table 'vote_pins'"))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS reddit_seen (
+                  post_id         TEXT PRIMARY KEY,
+                  time            INTEGER
+                  );",
+        rusqlite::NO_PARAMS,
+    ).map_err(|_| error!(DBError, "Failed to create table 'reddit_seen'"))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS watch_subscriptions (
+                  user_id         INTEGER,
+                  rule            TEXT,
+                  PRIMARY KEY (user_id, rule)
+                  );",
+        rusqlite::NO_PARAMS,
+    ).map_err(|_| error!(DBError, "Failed to create table 'watch_subscriptions'"))?;
+    conn.commit().map_err(|_| error!(DBError, "Failed to commit tables"))
 }
 
 /// Add a wa message to the database. Return the number of wa-s for
@@ -48,28 +385,97 @@ pub fn addWa(wa: WaEntry) -> Result<u32, Error>
     let conn = connect()?;
 
     let count: u32 = conn.query_row(
-        "SELECT COUNT(*) FROM was WHERE wa_to = ?1;",
-        rusqlite::params![wa.wa_to], |row| row.get(0))
+        "SELECT COUNT(*) FROM was WHERE chat_id = ?1 AND wa_to = ?2;",
+        rusqlite::params![wa.chat_id, wa.wa_to], |row| row.get(0))
         .map_err(|_| error!(DBError, "Failed to get count of was"))?;
 
     conn.execute(
-        "INSERT INTO was (id, wa_to, waer, time)
-         VALUES (?1, ?2, ?3, ?4);",
-        rusqlite::params![wa.id, wa.wa_to, wa.waer, wa.time.timestamp()])
+        "INSERT INTO was (chat_id, id, wa_to, waer, time)
+         VALUES (?1, ?2, ?3, ?4, ?5);",
+        rusqlite::params![wa.chat_id, wa.id, wa.wa_to, wa.waer,
+                          wa.time.timestamp()])
         .map_err(|_| error!(DBError, "Failed to add a wa"))?;
     Ok(count + 1)
 }
 
-/// Who did the most wa-s in the last `time_period`? Return the ID of
-/// the waer and the number of wa-s from this waer.
-pub fn bestWaer(time_period: chrono::Duration) -> Result<(i64, u32), Error>
+/// Add a wa message that is over the rate limit to the database.
+/// Flagged wa-s are kept apart from the normal ones, so they are not
+/// counted in any ranking.
+pub fn addFlaggedWa(wa: WaEntry, reason: &str) -> Result<(), Error>
+{
+    let conn = connect()?;
+    conn.execute(
+        "INSERT INTO flagged_was (chat_id, id, wa_to, waer, time, reason)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+        rusqlite::params![wa.chat_id, wa.id, wa.wa_to, wa.waer,
+                          wa.time.timestamp(), reason])
+        .map_err(|_| error!(DBError, "Failed to add a flagged wa"))?;
+    Ok(())
+}
+
+/// Return the number of wa-s from `waer` in the chat after `since`.
+pub fn waCountSince(chat_id: i64, waer: i64, since: DateTime) -> Result<u32, Error>
+{
+    let conn = connect()?;
+    conn.query_row(
+        "SELECT COUNT(*) FROM was WHERE chat_id = ?1 AND waer = ?2 AND time > ?3;",
+        rusqlite::params![chat_id, waer, since.timestamp()], |row| row.get(0))
+        .map_err(|_| error!(DBError, "Failed to get count of was"))
+}
+
+/// Return the time of the last wa from `waer` in the chat, if there
+/// is any. Flagged wa-s count too, so that a waer who keeps trying
+/// does not get one through after every gap.
+pub fn lastWaTime(chat_id: i64, waer: i64) -> Result<Option<DateTime>, Error>
+{
+    let conn = connect()?;
+    let time: Option<i64> = conn.query_row(
+        "SELECT MAX(time) FROM
+           (SELECT time FROM was WHERE chat_id = ?1 AND waer = ?2
+            UNION ALL
+            SELECT time FROM flagged_was WHERE chat_id = ?1 AND waer = ?2);",
+        rusqlite::params![chat_id, waer], |row| row.get(0))
+        .map_err(|_| error!(DBError, "Failed to get time of last wa"))?;
+    Ok(time.map(|t| chrono::Utc.timestamp(t, 0)))
+}
+
+/// Who have flagged wa-s in the chat in the last `time_period`?
+/// Return the IDs of the waers, their numbers of flagged wa-s, and the
+/// reason of their latest flagged wa, the most flagged waer first.
+pub fn flaggedWaers(chat_id: i64, time_period: chrono::Duration)
+                    -> Result<Vec<(i64, u32, String)>, Error>
+{
+    let now = chrono::offset::Utc::now();
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT waer, COUNT(*) as count,
+                (SELECT reason FROM flagged_was AS f
+                 WHERE f.chat_id = flagged_was.chat_id AND
+                       f.waer = flagged_was.waer AND f.time > ?2
+                 ORDER BY time DESC LIMIT 1)
+         FROM flagged_was WHERE chat_id = ?1 AND time > ?2
+         GROUP BY waer ORDER BY count DESC;")
+        .map_err(|_| error!(DBError, "Failed to prepare flagged was query"))?;
+    let rows = stmt.query_map(
+        rusqlite::params![chat_id, (now - time_period).timestamp()],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .map_err(|_| error!(DBError, "Failed to get flagged was"))?;
+
+    rows.collect::<Result<Vec<_>, _>>().map_err(
+        |_| error!(DBError, "Failed to read flagged was"))
+}
+
+/// Who did the most wa-s in the chat in the last `time_period`?
+/// Return the ID of the waer and the number of wa-s from this waer.
+pub fn bestWaer(chat_id: i64, time_period: chrono::Duration)
+                -> Result<(i64, u32), Error>
 {
     let now = chrono::offset::Utc::now();
     let conn = connect()?;
     let row = conn.query_row(
-        "SELECT waer, COUNT(*) as count FROM was WHERE time > ?1
+        "SELECT waer, COUNT(*) as count FROM was WHERE chat_id = ?1 AND time > ?2
          GROUP BY waer ORDER BY count DESC LIMIT 1;",
-        rusqlite::params![(now - time_period).timestamp()],
+        rusqlite::params![chat_id, (now - time_period).timestamp()],
         |row| Ok((row.get(0), row.get(1))))
         .map_err(|_| error!(DBError, "Failed to get best waer"))?;
 
@@ -77,19 +483,673 @@ pub fn bestWaer(time_period: chrono::Duration) -> Result<(i64, u32), Error>
         row.1.map_err(|_| error!(DBError, "Failed to get wa count"))?))
 }
 
-/// Which msg is the most wa-ed during the last `time_period`? Return
-/// msg ID and number of wa-s.
-pub fn bestWaable(time_period: chrono::Duration) -> Result<(i64, u32), Error>
+/// Which msg in the chat is the most wa-ed during the last
+/// `time_period`? Return msg ID and number of wa-s.
+pub fn bestWaable(chat_id: i64, time_period: chrono::Duration)
+                  -> Result<(i64, u32), Error>
 {
     let now = chrono::offset::Utc::now();
     let conn = connect()?;
     let row = conn.query_row(
-        "SELECT wa_to, COUNT(*) as count FROM was WHERE time > ?1
+        "SELECT wa_to, COUNT(*) as count FROM was WHERE chat_id = ?1 AND time > ?2
          GROUP BY wa_to ORDER BY count DESC LIMIT 1;",
-        rusqlite::params![(now - time_period).timestamp()],
+        rusqlite::params![chat_id, (now - time_period).timestamp()],
         |row| Ok((row.get(0), row.get(1))))
         .map_err(|_| error!(DBError, "Failed to get best waable"))?;
 
     Ok((row.0.map_err(|_| error!(DBError, "Failed to get wa_to"))?,
         row.1.map_err(|_| error!(DBError, "Failed to get wa count"))?))
 }
+
+/// Record a daily picture sent by the bot.
+pub fn addDailyPic(pic: DailyPicEntry) -> Result<(), Error>
+{
+    let conn = connect()?;
+    conn.execute(
+        "INSERT INTO daily_pics (chat_id, msg_id, post_id, title, author, url, time)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);",
+        rusqlite::params![pic.chat_id, pic.msg_id, pic.post_id, pic.title,
+                          pic.author, pic.url, pic.time.timestamp()])
+        .map_err(|_| error!(DBError, "Failed to add a daily pic"))?;
+    Ok(())
+}
+
+/// Which daily pictures sent to the chat during the last
+/// `time_period` are the most wa-ed? Return at most `limit` pictures
+/// and their numbers of wa-s, the most wa-ed first.
+pub fn bestDailyPics(chat_id: i64, time_period: chrono::Duration, limit: u32)
+                     -> Result<Vec<(DailyPicEntry, u32)>, Error>
+{
+    let now = chrono::offset::Utc::now();
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT daily_pics.msg_id, post_id, title, author, url,
+                daily_pics.time, COUNT(was.id) as count
+         FROM daily_pics JOIN was ON was.chat_id = daily_pics.chat_id AND
+                                     was.wa_to = daily_pics.msg_id
+         WHERE daily_pics.chat_id = ?1 AND daily_pics.time > ?2
+         GROUP BY daily_pics.msg_id ORDER BY count DESC LIMIT ?3;")
+        .map_err(|_| error!(DBError, "Failed to prepare best daily pics query"))?;
+    let rows = stmt.query_map(
+        rusqlite::params![chat_id, (now - time_period).timestamp(), limit],
+        |row| {
+            let time: i64 = row.get(5)?;
+            Ok((DailyPicEntry {
+                chat_id: chat_id,
+                msg_id: row.get(0)?,
+                post_id: row.get(1)?,
+                title: row.get(2)?,
+                author: row.get(3)?,
+                url: row.get(4)?,
+                time: chrono::Utc.timestamp(time, 0),
+            }, row.get(6)?))
+        }).map_err(|_| error!(DBError, "Failed to get best daily pics"))?;
+
+    rows.collect::<Result<Vec<_>, _>>().map_err(
+        |_| error!(DBError, "Failed to read best daily pics"))
+}
+
+/// Claim the message with `msg_id` in the chat for the hall of fame.
+/// Return false if the message is already in the hall of fame.
+pub fn claimHallOfFame(chat_id: i64, msg_id: i64, author: i64, time: DateTime)
+                       -> Result<bool, Error>
+{
+    let conn = connect()?;
+    let count = conn.execute(
+        "INSERT OR IGNORE INTO hall_of_fame (chat_id, msg_id, author, time)
+         VALUES (?1, ?2, ?3, ?4);",
+        rusqlite::params![chat_id, msg_id, author, time.timestamp()])
+        .map_err(|_| error!(DBError, "Failed to claim hall of fame"))?;
+    Ok(count > 0)
+}
+
+/// Record the ID of the forwarded message in the hall of fame
+/// channel for the message with `msg_id` in the chat.
+pub fn setHallOfFameForward(chat_id: i64, msg_id: i64, forwarded_id: i64)
+                            -> Result<(), Error>
+{
+    let conn = connect()?;
+    conn.execute(
+        "UPDATE hall_of_fame SET forwarded_id = ?3 WHERE chat_id = ?1 AND msg_id = ?2;",
+        rusqlite::params![chat_id, msg_id, forwarded_id])
+        .map_err(|_| error!(DBError, "Failed to update hall of fame"))?;
+    Ok(())
+}
+
+/// Remove the message with `msg_id` in the chat from the hall of
+/// fame, so that it can be claimed again.
+pub fn releaseHallOfFame(chat_id: i64, msg_id: i64) -> Result<(), Error>
+{
+    let conn = connect()?;
+    conn.execute("DELETE FROM hall_of_fame WHERE chat_id = ?1 AND msg_id = ?2;",
+                 rusqlite::params![chat_id, msg_id])
+        .map_err(|_| error!(DBError, "Failed to release hall of fame"))?;
+    Ok(())
+}
+
+/// Return the message pinned by the bot in the chat, if there is one.
+pub fn getPinState(chat_id: i64) -> Result<Option<PinState>, Error>
+{
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT bot_pin, original_pin FROM pins WHERE chat_id = ?1;")
+        .map_err(|_| error!(DBError, "Failed to prepare pin query"))?;
+    let mut rows = stmt.query_map(
+        rusqlite::params![chat_id],
+        |row| Ok(PinState {
+            chat_id: chat_id,
+            bot_pin: row.get(0)?,
+            original_pin: row.get(1)?,
+        })).map_err(|_| error!(DBError, "Failed to get pin state"))?;
+    rows.next().transpose().map_err(
+        |_| error!(DBError, "Failed to read pin state"))
+}
+
+pub fn setPinState(state: PinState) -> Result<(), Error>
+{
+    let conn = connect()?;
+    conn.execute(
+        "INSERT OR REPLACE INTO pins (chat_id, bot_pin, original_pin)
+         VALUES (?1, ?2, ?3);",
+        rusqlite::params![state.chat_id, state.bot_pin, state.original_pin])
+        .map_err(|_| error!(DBError, "Failed to set pin state"))?;
+    Ok(())
+}
+
+pub fn clearPinState(chat_id: i64) -> Result<(), Error>
+{
+    let conn = connect()?;
+    conn.execute("DELETE FROM pins WHERE chat_id = ?1;",
+                 rusqlite::params![chat_id])
+        .map_err(|_| error!(DBError, "Failed to clear pin state"))?;
+    Ok(())
+}
+
+pub fn addCaptcha(captcha: CaptchaEntry) -> Result<(), Error>
+{
+    let conn = connect()?;
+    conn.execute(
+        "INSERT OR REPLACE INTO captchas (chat_id, user_id, msg_id, answer, deadline)
+         VALUES (?1, ?2, ?3, ?4, ?5);",
+        rusqlite::params![captcha.chat_id, captcha.user_id, captcha.msg_id,
+                          captcha.answer, captcha.deadline.timestamp()])
+        .map_err(|_| error!(DBError, "Failed to add a captcha"))?;
+    Ok(())
+}
+
+fn captchaFromRow(row: &rusqlite::Row) -> rusqlite::Result<CaptchaEntry>
+{
+    let deadline: i64 = row.get(4)?;
+    Ok(CaptchaEntry {
+        chat_id: row.get(0)?,
+        user_id: row.get(1)?,
+        msg_id: row.get(2)?,
+        answer: row.get(3)?,
+        deadline: chrono::Utc.timestamp(deadline, 0),
+    })
+}
+
+/// Return the pending captcha for the user in the chat, or `None` if
+/// there is no such captcha, for example if it has been answered or
+/// has expired.
+pub fn getCaptcha(chat_id: i64, user_id: i64) -> Result<Option<CaptchaEntry>, Error>
+{
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT chat_id, user_id, msg_id, answer, deadline FROM captchas
+         WHERE chat_id = ?1 AND user_id = ?2;")
+        .map_err(|_| error!(DBError, "Failed to prepare captcha query"))?;
+    let mut rows = stmt.query_map(rusqlite::params![chat_id, user_id],
+                                  captchaFromRow)
+        .map_err(|_| error!(DBError, "Failed to get captcha"))?;
+    rows.next().transpose().map_err(
+        |_| error!(DBError, "Failed to read captcha"))
+}
+
+/// Remove the pending captcha with the question in message `msg_id`.
+/// Return false if it is already removed, or replaced by a newer
+/// captcha after the user rejoined.
+pub fn removeCaptcha(chat_id: i64, user_id: i64, msg_id: i64) -> Result<bool, Error>
+{
+    let conn = connect()?;
+    let count = conn.execute(
+        "DELETE FROM captchas WHERE chat_id = ?1 AND user_id = ?2 AND msg_id = ?3;",
+        rusqlite::params![chat_id, user_id, msg_id])
+        .map_err(|_| error!(DBError, "Failed to remove captcha"))?;
+    Ok(count > 0)
+}
+
+/// Remove the pending captcha for the user in the chat, and return
+/// it. Return `None` if there is no such captcha.
+pub fn takeCaptcha(chat_id: i64, user_id: i64) -> Result<Option<CaptchaEntry>, Error>
+{
+    let captcha = match getCaptcha(chat_id, user_id)?
+    {
+        Some(captcha) => captcha,
+        None => return Ok(None),
+    };
+    // Someone else might have taken it in between.
+    if removeCaptcha(chat_id, user_id, captcha.msg_id)?
+    {
+        Ok(Some(captcha))
+    }
+    else
+    {
+        Ok(None)
+    }
+}
+
+/// Return all the pending captchas.
+pub fn pendingCaptchas() -> Result<Vec<CaptchaEntry>, Error>
+{
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT chat_id, user_id, msg_id, answer, deadline FROM captchas;")
+        .map_err(|_| error!(DBError, "Failed to prepare captcha query"))?;
+    let rows = stmt.query_map(rusqlite::NO_PARAMS, captchaFromRow)
+        .map_err(|_| error!(DBError, "Failed to get captchas"))?;
+    rows.collect::<Result<Vec<_>, _>>().map_err(
+        |_| error!(DBError, "Failed to read captchas"))
+}
+
+pub fn addMemberJoin(member: MemberEntry) -> Result<(), Error>
+{
+    let conn = connect()?;
+    conn.execute(
+        "INSERT INTO members (chat_id, user_id, first_name, last_name, username,
+                              join_time, added_by)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);",
+        rusqlite::params![member.chat_id, member.user_id, member.first_name,
+                          member.last_name, member.username,
+                          member.time.timestamp(), member.added_by])
+        .map_err(|_| error!(DBError, "Failed to add a member join"))?;
+    Ok(())
+}
+
+/// Record that a member left. If the member joined before the
+/// history started, the record has no join time.
+pub fn addMemberLeave(member: MemberEntry) -> Result<(), Error>
+{
+    let conn = connect()?;
+    let count = conn.execute(
+        "UPDATE members SET leave_time = ?3, first_name = ?4, last_name = ?5,
+                            username = ?6
+         WHERE id = (SELECT MAX(id) FROM members
+                     WHERE chat_id = ?1 AND user_id = ?2 AND leave_time IS NULL);",
+        rusqlite::params![member.chat_id, member.user_id, member.time.timestamp(),
+                          member.first_name, member.last_name, member.username])
+        .map_err(|_| error!(DBError, "Failed to update a member leave"))?;
+    if count > 0
+    {
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT INTO members (chat_id, user_id, first_name, last_name, username,
+                              leave_time)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+        rusqlite::params![member.chat_id, member.user_id, member.first_name,
+                          member.last_name, member.username,
+                          member.time.timestamp()])
+        .map_err(|_| error!(DBError, "Failed to add a member leave"))?;
+    Ok(())
+}
+
+/// How many members joined and left the chat during the last
+/// `time_period`? Return (number of joins, number of leaves).
+pub fn memberGrowth(chat_id: i64, time_period: chrono::Duration)
+                    -> Result<(u32, u32), Error>
+{
+    let since = (chrono::offset::Utc::now() - time_period).timestamp();
+    let conn = connect()?;
+    let joined: u32 = conn.query_row(
+        "SELECT COUNT(*) FROM members WHERE chat_id = ?1 AND join_time > ?2;",
+        rusqlite::params![chat_id, since], |row| row.get(0))
+        .map_err(|_| error!(DBError, "Failed to get count of joins"))?;
+    let left: u32 = conn.query_row(
+        "SELECT COUNT(*) FROM members WHERE chat_id = ?1 AND leave_time > ?2;",
+        rusqlite::params![chat_id, since], |row| row.get(0))
+        .map_err(|_| error!(DBError, "Failed to get count of leaves"))?;
+    Ok((joined, left))
+}
+
+/// Return the last known full name of the user, if the user has ever
+/// joined or left a chat with the bot.
+pub fn cachedMemberName(user_id: i64) -> Result<Option<String>, Error>
+{
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT first_name, last_name FROM members WHERE user_id = ?1
+         ORDER BY id DESC LIMIT 1;")
+        .map_err(|_| error!(DBError, "Failed to prepare member name query"))?;
+    let mut rows = stmt.query_map(
+        rusqlite::params![user_id],
+        |row| {
+            let first: String = row.get(0)?;
+            let last: Option<String> = row.get(1)?;
+            Ok(match last
+               {
+                   Some(last) => format!("{} {}", first, last),
+                   None => first,
+               })
+        }).map_err(|_| error!(DBError, "Failed to get member name"))?;
+    rows.next().transpose().map_err(
+        |_| error!(DBError, "Failed to read member name"))
+}
+
+/// Remember to delete the message with `msg_id` in the chat at `due`.
+pub fn addDeletion(chat_id: i64, msg_id: i64, due: DateTime) -> Result<(), Error>
+{
+    let conn = connect()?;
+    conn.execute(
+        "INSERT OR REPLACE INTO deletions (chat_id, msg_id, due)
+         VALUES (?1, ?2, ?3);",
+        rusqlite::params![chat_id, msg_id, due.timestamp()])
+        .map_err(|_| error!(DBError, "Failed to add a deletion"))?;
+    Ok(())
+}
+
+pub fn removeDeletion(chat_id: i64, msg_id: i64) -> Result<(), Error>
+{
+    let conn = connect()?;
+    conn.execute("DELETE FROM deletions WHERE chat_id = ?1 AND msg_id = ?2;",
+                 rusqlite::params![chat_id, msg_id])
+        .map_err(|_| error!(DBError, "Failed to remove a deletion"))?;
+    Ok(())
+}
+
+/// Return all the scheduled deletions as (chat ID, message ID, due
+/// time).
+pub fn pendingDeletions() -> Result<Vec<(i64, i64, DateTime)>, Error>
+{
+    let conn = connect()?;
+    let mut stmt = conn.prepare("SELECT chat_id, msg_id, due FROM deletions;")
+        .map_err(|_| error!(DBError, "Failed to prepare deletion query"))?;
+    let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| {
+        let due: i64 = row.get(2)?;
+        Ok((row.get(0)?, row.get(1)?, chrono::Utc.timestamp(due, 0)))
+    }).map_err(|_| error!(DBError, "Failed to get deletions"))?;
+    rows.collect::<Result<Vec<_>, _>>().map_err(
+        |_| error!(DBError, "Failed to read deletions"))
+}
+
+/// Add new members to welcome in the chat at `due`, as (user ID, user
+/// in JSON). Return true if no one else is waiting in the chat, and so
+/// the caller should send the welcome at `due`.
+pub fn addPendingWelcomes(chat_id: i64, users: &[(i64, String)], due: DateTime)
+                          -> Result<bool, Error>
+{
+    let mut conn = connect()?;
+    // Check and add at once, so that only one caller sends the welcome.
+    let tx = conn.transaction_with_behavior(
+        rusqlite::TransactionBehavior::Immediate)
+        .map_err(|_| error!(DBError, "Failed to start transaction"))?;
+    let count: u32 = tx.query_row(
+        "SELECT COUNT(*) FROM pending_welcomes WHERE chat_id = ?1;",
+        rusqlite::params![chat_id], |row| row.get(0))
+        .map_err(|_| error!(DBError, "Failed to get pending welcomes"))?;
+    for (user_id, user) in users
+    {
+        tx.execute(
+            "INSERT OR REPLACE INTO pending_welcomes (chat_id, user_id, user, due)
+             VALUES (?1, ?2, ?3, ?4);",
+            rusqlite::params![chat_id, user_id, user, due.timestamp()])
+            .map_err(|_| error!(DBError, "Failed to add a pending welcome"))?;
+    }
+    tx.commit().map_err(|_| error!(DBError, "Failed to add pending welcomes"))?;
+    Ok(count == 0)
+}
+
+/// Remove the members waiting to be welcomed in the chat, and return
+/// them in JSON.
+pub fn takePendingWelcomes(chat_id: i64) -> Result<Vec<String>, Error>
+{
+    let mut conn = connect()?;
+    let tx = conn.transaction_with_behavior(
+        rusqlite::TransactionBehavior::Immediate)
+        .map_err(|_| error!(DBError, "Failed to start transaction"))?;
+    let users = {
+        let mut stmt = tx.prepare(
+            "SELECT user FROM pending_welcomes WHERE chat_id = ?1 ORDER BY due;")
+            .map_err(|_| error!(DBError, "Failed to prepare welcome query"))?;
+        let rows = stmt.query_map(rusqlite::params![chat_id], |row| row.get(0))
+            .map_err(|_| error!(DBError, "Failed to get pending welcomes"))?;
+        rows.collect::<Result<Vec<String>, _>>().map_err(
+            |_| error!(DBError, "Failed to read pending welcomes"))?
+    };
+    tx.execute("DELETE FROM pending_welcomes WHERE chat_id = ?1;",
+               rusqlite::params![chat_id])
+        .map_err(|_| error!(DBError, "Failed to remove pending welcomes"))?;
+    tx.commit().map_err(|_| error!(DBError, "Failed to take pending welcomes"))?;
+    Ok(users)
+}
+
+/// Return the chats with members waiting to be welcomed, and when the
+/// first of them is due.
+pub fn pendingWelcomeChats() -> Result<Vec<(i64, DateTime)>, Error>
+{
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT chat_id, MIN(due) FROM pending_welcomes GROUP BY chat_id;")
+        .map_err(|_| error!(DBError, "Failed to prepare welcome query"))?;
+    let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| {
+        let due: i64 = row.get(1)?;
+        Ok((row.get(0)?, chrono::Utc.timestamp(due, 0)))
+    }).map_err(|_| error!(DBError, "Failed to get pending welcomes"))?;
+    rows.collect::<Result<Vec<_>, _>>().map_err(
+        |_| error!(DBError, "Failed to read pending welcomes"))
+}
+
+/// Has a post with any of the IDs `post_ids`, or with media at the
+/// canonical URL `media_url`, been sent to the chat? The posts can be
+/// from any daily picture source.
+pub fn isPostSent(chat_id: i64, post_ids: &[&str], media_url: &str)
+                  -> Result<bool, Error>
+{
+    let conn = connect()?;
+    let count: u32 = conn.query_row(
+        "SELECT COUNT(*) FROM posted_reddit WHERE chat_id = ?1 AND media_url = ?2;",
+        rusqlite::params![chat_id, media_url], |row| row.get(0))
+        .map_err(|_| error!(DBError, "Failed to query sent media"))?;
+    if count > 0
+    {
+        return Ok(true);
+    }
+    for post_id in post_ids
+    {
+        let count: u32 = conn.query_row(
+            "SELECT COUNT(*) FROM posted_reddit WHERE chat_id = ?1 AND fullname = ?2;",
+            rusqlite::params![chat_id, post_id], |row| row.get(0))
+            .map_err(|_| error!(DBError, "Failed to query sent posts"))?;
+        if count > 0
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Remember that the post with ID `post_id` has been sent to the chat.
+pub fn addSentPost(chat_id: i64, post_id: &str, media_url: &str,
+                   time: DateTime) -> Result<(), Error>
+{
+    let conn = connect()?;
+    conn.execute(
+        "INSERT OR REPLACE INTO posted_reddit (chat_id, fullname, media_url, time)
+         VALUES (?1, ?2, ?3, ?4);",
+        rusqlite::params![chat_id, post_id, media_url, time.timestamp()])
+        .map_err(|_| error!(DBError, "Failed to add a sent post"))?;
+    Ok(())
+}
+
+/// Add a poll of the daily picture with its candidates.
+pub fn addPoll(poll: PollEntry, options: &[PollOptionEntry]) -> Result<(), Error>
+{
+    let conn = connect()?;
+    conn.execute(
+        "INSERT OR REPLACE INTO polls (chat_id, msg_id, poll_id, close_time, closed)
+         VALUES (?1, ?2, ?3, ?4, 0);",
+        rusqlite::params![poll.chat_id, poll.msg_id, poll.poll_id,
+                          poll.close_time.timestamp()])
+        .map_err(|_| error!(DBError, "Failed to add a poll"))?;
+    for option in options
+    {
+        conn.execute(
+            "INSERT OR REPLACE INTO poll_options
+             (chat_id, poll_msg_id, idx, album_msg_id, post_id, title, author,
+              url, caption, score, votes, media_url)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12);",
+            rusqlite::params![poll.chat_id, poll.msg_id, option.index,
+                              option.album_msg_id, option.post_id, option.title,
+                              option.author, option.url, option.caption,
+                              option.score, option.votes, option.media_url])
+            .map_err(|_| error!(DBError, "Failed to add a poll option"))?;
+    }
+    Ok(())
+}
+
+/// Return the open polls that should be closed at `now`.
+pub fn duePolls(now: DateTime) -> Result<Vec<PollEntry>, Error>
+{
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT chat_id, msg_id, poll_id, close_time FROM polls
+         WHERE closed = 0 AND close_time <= ?1;")
+        .map_err(|_| error!(DBError, "Failed to prepare poll query"))?;
+    let rows = stmt.query_map(rusqlite::params![now.timestamp()], |row| {
+        let close_time: i64 = row.get(3)?;
+        Ok(PollEntry {
+            chat_id: row.get(0)?,
+            msg_id: row.get(1)?,
+            poll_id: row.get(2)?,
+            close_time: chrono::Utc.timestamp(close_time, 0),
+        })
+    }).map_err(|_| error!(DBError, "Failed to get polls"))?;
+    rows.collect::<Result<Vec<_>, _>>().map_err(
+        |_| error!(DBError, "Failed to read polls"))
+}
+
+/// Mark the poll closed, with the votes of the options. Return false
+/// if it is already closed.
+pub fn closePoll(chat_id: i64, msg_id: i64, votes: &[i64]) -> Result<bool, Error>
+{
+    let conn = connect()?;
+    let count = conn.execute(
+        "UPDATE polls SET closed = 1 WHERE chat_id = ?1 AND msg_id = ?2 AND closed = 0;",
+        rusqlite::params![chat_id, msg_id])
+        .map_err(|_| error!(DBError, "Failed to close poll"))?;
+    if count == 0
+    {
+        return Ok(false);
+    }
+    for (index, count) in votes.iter().enumerate()
+    {
+        conn.execute(
+            "UPDATE poll_options SET votes = ?1
+             WHERE chat_id = ?2 AND poll_msg_id = ?3 AND idx = ?4;",
+            rusqlite::params![count, chat_id, msg_id, index as i64])
+            .map_err(|_| error!(DBError, "Failed to set votes"))?;
+    }
+    Ok(true)
+}
+
+fn pollOptionFromRow(row: &rusqlite::Row) -> rusqlite::Result<PollOptionEntry>
+{
+    Ok(PollOptionEntry {
+        index: row.get(0)?,
+        album_msg_id: row.get(1)?,
+        post_id: row.get(2)?,
+        title: row.get(3)?,
+        author: row.get(4)?,
+        url: row.get(5)?,
+        caption: row.get(6)?,
+        score: row.get(7)?,
+        votes: row.get(8)?,
+        media_url: row.get::<_, Option<String>>(9)?.unwrap_or_default(),
+    })
+}
+
+/// Return the options of the poll in order.
+pub fn pollOptions(chat_id: i64, msg_id: i64) -> Result<Vec<PollOptionEntry>, Error>
+{
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT idx, album_msg_id, post_id, title, author, url, caption, score,
+         votes, media_url FROM poll_options WHERE chat_id = ?1 AND poll_msg_id = ?2
+         ORDER BY idx;")
+        .map_err(|_| error!(DBError, "Failed to prepare poll option query"))?;
+    let rows = stmt.query_map(rusqlite::params![chat_id, msg_id],
+                              pollOptionFromRow)
+        .map_err(|_| error!(DBError, "Failed to get poll options"))?;
+    rows.collect::<Result<Vec<_>, _>>().map_err(
+        |_| error!(DBError, "Failed to read poll options"))
+}
+
+/// Return the options of the closed polls in the chat, one vector
+/// per poll.
+pub fn closedPollOptions(chat_id: i64) -> Result<Vec<Vec<PollOptionEntry>>, Error>
+{
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT msg_id FROM polls WHERE chat_id = ?1 AND closed = 1
+         ORDER BY msg_id;")
+        .map_err(|_| error!(DBError, "Failed to prepare poll query"))?;
+    let rows = stmt.query_map(rusqlite::params![chat_id], |row| row.get(0))
+        .map_err(|_| error!(DBError, "Failed to get polls"))?;
+    let msg_ids = rows.collect::<Result<Vec<i64>, _>>().map_err(
+        |_| error!(DBError, "Failed to read polls"))?;
+    msg_ids.into_iter().map(|msg_id| pollOptions(chat_id, msg_id)).collect()
+}
+
+/// Has the watcher seen the Reddit post?
+pub fn isRedditSeen(post_id: &str) -> Result<bool, Error>
+{
+    let conn = connect()?;
+    let count: u32 = conn.query_row(
+        "SELECT COUNT(*) FROM reddit_seen WHERE post_id = ?1;",
+        rusqlite::params![post_id], |row| row.get(0))
+        .map_err(|_| error!(DBError, "Failed to query seen posts"))?;
+    Ok(count > 0)
+}
+
+/// Return the winner of a vote last pinned in the chat, if any.
+pub fn getVotePin(chat_id: i64) -> Result<Option<i64>, Error>
+{
+    let conn = connect()?;
+    let mut stmt = conn.prepare("SELECT msg_id FROM vote_pins WHERE chat_id = ?1;")
+        .map_err(|_| error!(DBError, "Failed to prepare vote pin query"))?;
+    let mut rows = stmt.query_map(rusqlite::params![chat_id], |row| row.get(0))
+        .map_err(|_| error!(DBError, "Failed to get vote pin"))?;
+    rows.next().transpose().map_err(
+        |_| error!(DBError, "Failed to read vote pin"))
+}
+
+pub fn setVotePin(chat_id: i64, msg_id: i64) -> Result<(), Error>
+{
+    let conn = connect()?;
+    conn.execute(
+        "INSERT OR REPLACE INTO vote_pins (chat_id, msg_id) VALUES (?1, ?2);",
+        rusqlite::params![chat_id, msg_id])
+        .map_err(|_| error!(DBError, "Failed to set vote pin"))?;
+    Ok(())
+}
+
+/// Remember that the watcher has seen the Reddit post.
+pub fn markRedditSeen(post_id: &str, time: DateTime) -> Result<(), Error>
+{
+    let conn = connect()?;
+    conn.execute(
+        "INSERT OR IGNORE INTO reddit_seen (post_id, time) VALUES (?1, ?2);",
+        rusqlite::params![post_id, time.timestamp()])
+        .map_err(|_| error!(DBError, "Failed to mark post seen"))?;
+    Ok(())
+}
+
+/// Forget the posts seen before `before`.
+pub fn pruneRedditSeen(before: DateTime) -> Result<(), Error>
+{
+    let conn = connect()?;
+    conn.execute("DELETE FROM reddit_seen WHERE time < ?1;",
+                 rusqlite::params![before.timestamp()])
+        .map_err(|_| error!(DBError, "Failed to prune seen posts"))?;
+    Ok(())
+}
+
+pub fn addWatchSubscription(user_id: i64, rule: &str) -> Result<(), Error>
+{
+    let conn = connect()?;
+    conn.execute(
+        "INSERT OR IGNORE INTO watch_subscriptions (user_id, rule) VALUES (?1, ?2);",
+        rusqlite::params![user_id, rule])
+        .map_err(|_| error!(DBError, "Failed to add subscription"))?;
+    Ok(())
+}
+
+/// Remove the subscription of the user to `rule`, or to all rules if
+/// `rule` is `None`.
+pub fn removeWatchSubscription(user_id: i64, rule: Option<&str>) -> Result<(), Error>
+{
+    let conn = connect()?;
+    match rule
+    {
+        Some(rule) => conn.execute(
+            "DELETE FROM watch_subscriptions WHERE user_id = ?1 AND rule = ?2;",
+            rusqlite::params![user_id, rule]),
+        None => conn.execute(
+            "DELETE FROM watch_subscriptions WHERE user_id = ?1;",
+            rusqlite::params![user_id]),
+    }.map_err(|_| error!(DBError, "Failed to remove subscription"))?;
+    Ok(())
+}
+
+/// Return the users subscribed to `rule`.
+pub fn watchSubscribers(rule: &str) -> Result<Vec<i64>, Error>
+{
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT user_id FROM watch_subscriptions WHERE rule = ?1;")
+        .map_err(|_| error!(DBError, "Failed to prepare subscription query"))?;
+    let rows = stmt.query_map(rusqlite::params![rule], |row| row.get(0))
+        .map_err(|_| error!(DBError, "Failed to get subscribers"))?;
+    rows.collect::<Result<Vec<i64>, _>>().map_err(
+        |_| error!(DBError, "Failed to read subscribers"))
+}
@@ -0,0 +1,370 @@
+use std::fs;
+use std::path::Path;
+
+use rand::prelude::*;
+use log::debug;
+use futures::future::{BoxFuture, FutureExt};
+use futures::TryStreamExt;
+use regex::Regex;
+use chrono::prelude::*;
+
+use crate::error::Error;
+use crate::reddit;
+use crate::bot_config;
+
+/// A candidate of the daily picture, from any source.
+pub struct MediaPost
+{
+    /// ID of the post, unique among all sources.
+    pub id: String,
+    /// ID of the post this is a copy of, e.g. the post it crossposts.
+    pub origin_id: Option<String>,
+    pub title: String,
+    pub author: String,
+    /// ID of the author that does not change when the name does, if
+    /// the source has one.
+    pub author_fullname: Option<String>,
+    pub score: i32,
+    pub count_comments: u32,
+    pub upvote_ratio: f64,
+    pub flair: Option<String>,
+    pub over_18: bool,
+    pub spoiler: bool,
+    pub media: reddit::PostMedia,
+    /// Link to the post. Empty if the post is not on the web.
+    pub url: String,
+    pub time_create: DateTime<Utc>,
+    /// Where the post is from, e.g. the subreddit.
+    pub community: String,
+}
+
+impl MediaPost
+{
+    /// Return the URI to the media in the post, in a form that is the
+    /// same for the same media, e.g. in crossposts or in different
+    /// sizes of previews.
+    pub fn canonicalMediaUrl(&self) -> String
+    {
+        let uri = match &self.media
+        {
+            reddit::PostMedia::Image(uri) | reddit::PostMedia::Video(uri)
+                | reddit::PostMedia::Gif(uri) | reddit::PostMedia::Link(uri) =>
+                uri.as_str(),
+            reddit::PostMedia::Gallery(uris) => uris.first()
+                .map(|u| u.as_str()).unwrap_or(""),
+            reddit::PostMedia::Text => self.url.as_str(),
+        };
+        reddit::canonicalUrl(uri)
+    }
+}
+
+impl From<reddit::Post> for MediaPost
+{
+    fn from(post: reddit::Post) -> Self
+    {
+        Self {
+            url: post.shortUrl(),
+            id: post.id,
+            origin_id: post.crosspost_parent,
+            title: post.title,
+            author: post.author,
+            author_fullname: post.author_fullname,
+            score: post.score,
+            count_comments: post.count_comments,
+            upvote_ratio: post.upvote_ratio,
+            flair: post.link_flair_text,
+            over_18: post.over_18,
+            spoiler: post.spoiler,
+            media: post.media,
+            time_create: post.time_create,
+            community: post.sub,
+        }
+    }
+}
+
+/// Something that provides candidates of the daily picture.
+pub trait ContentSource: Send + Sync
+{
+    /// Name of the source in logs.
+    fn name(&self) -> String;
+
+    /// Get the posts made after `since`. Sources that cannot tell
+    /// when a post is made may return older posts.
+    fn fetch<'a>(&'a self, since: DateTime<Utc>)
+                 -> BoxFuture<'a, Result<Vec<MediaPost>, Error>>;
+}
+
+/// The top posts in a subreddit in a time range.
+pub struct RedditContent<'q>
+{
+    querier: &'q reddit::RedditQuerier,
+    subreddit: String,
+    time_range: reddit::TimeRange,
+    max_pages: u32,
+}
+
+impl<'q> RedditContent<'q>
+{
+    pub fn new(querier: &'q reddit::RedditQuerier, subreddit: &str,
+               time_range: reddit::TimeRange, max_pages: u32) -> Self
+    {
+        Self {
+            querier: querier,
+            subreddit: subreddit.to_owned(),
+            time_range: time_range,
+            max_pages: max_pages,
+        }
+    }
+}
+
+impl<'q> ContentSource for RedditContent<'q>
+{
+    fn name(&self) -> String
+    {
+        format!("r/{}", self.subreddit)
+    }
+
+    fn fetch<'a>(&'a self, since: DateTime<Utc>)
+                 -> BoxFuture<'a, Result<Vec<MediaPost>, Error>>
+    {
+        async move {
+            let sub = reddit::Subreddit::new(&self.subreddit);
+            debug!("Getting posts from r/{}...", self.subreddit);
+            let posts: Vec<reddit::Post> = sub.stream(
+                self.querier, reddit::PostSorting::top,
+                Some(self.time_range), self.max_pages, Some(since))
+                .try_collect().await?;
+            Ok(posts.into_iter().map(MediaPost::from).collect())
+        }.boxed()
+    }
+}
+
+/// Posts with images in an RSS or Atom feed.
+pub struct FeedContent
+{
+    name: String,
+    url: String,
+    /// Finds the `<img>` in the content of an entry.
+    img_pattern: Regex,
+}
+
+impl FeedContent
+{
+    pub fn new(name: &str, url: &str) -> Result<Self, Error>
+    {
+        let img_pattern = Regex::new(r#"<img[^>]*\ssrc\s*=\s*["']([^"']+)["']"#)
+            .map_err(|e| error!(RuntimeError, format!("Invalid img regex: {}", e)))?;
+        Ok(Self {
+            name: name.to_owned(),
+            url: url.to_owned(),
+            img_pattern: img_pattern,
+        })
+    }
+
+    fn parse(&self, body: &[u8]) -> Result<Vec<MediaPost>, Error>
+    {
+        let feed = feed_rs::parser::parse(body).map_err(
+            |e| error!(RuntimeError, format!("Failed to parse feed {}: {}",
+                                             self.name, e)))?;
+        Ok(feed.entries.into_iter()
+           .filter_map(|entry| postFromEntry(&self.name, &self.img_pattern, entry))
+           .collect())
+    }
+}
+
+/// Find the images in a feed entry, from its media elements, or from
+/// the first `<img>` in its content that `img_pattern` finds.
+fn imagesInEntry(entry: &feed_rs::model::Entry, img_pattern: &Regex) -> Vec<String>
+{
+    let mut images: Vec<String> = entry.media.iter()
+        .flat_map(|m| m.content.iter())
+        .filter(|c| match &c.content_type
+                {
+                    Some(t) => t.to_string().starts_with("image/"),
+                    None => true,
+                })
+        .filter_map(|c| c.url.as_ref().map(|u| u.to_string()))
+        .collect();
+    if images.is_empty()
+    {
+        images = entry.media.iter().flat_map(|m| m.thumbnails.iter())
+            .map(|t| t.image.uri.clone()).take(1).collect();
+    }
+    if images.is_empty()
+    {
+        let html = entry.content.as_ref().and_then(|c| c.body.clone())
+            .or_else(|| entry.summary.as_ref().map(|s| s.content.clone()))
+            .unwrap_or_default();
+        images = img_pattern.captures(&html).map(|cap| cap[1].to_owned())
+            .into_iter().collect();
+    }
+    images
+}
+
+/// Make a post from a feed entry. Return `None` if the entry has no
+/// image.
+fn postFromEntry(feed_name: &str, img_pattern: &Regex, entry: feed_rs::model::Entry)
+                 -> Option<MediaPost>
+{
+    let mut images = imagesInEntry(&entry, img_pattern);
+    let media = match images.len()
+    {
+        0 => return None,
+        1 => reddit::PostMedia::Image(images.remove(0)),
+        _ => reddit::PostMedia::Gallery(images),
+    };
+    Some(MediaPost {
+        origin_id: None,
+        title: entry.title.map(|t| t.content).unwrap_or_default(),
+        author: entry.authors.first().map(|p| p.name.clone())
+            .unwrap_or_default(),
+        author_fullname: None,
+        score: 1,
+        count_comments: 0,
+        upvote_ratio: 1.0,
+        flair: entry.categories.first().map(|c| c.term.clone()),
+        over_18: false,
+        spoiler: false,
+        media: media,
+        url: entry.links.first().map(|l| l.href.clone()).unwrap_or_default(),
+        time_create: entry.published.or(entry.updated)
+            .unwrap_or_else(Utc::now),
+        community: feed_name.to_owned(),
+        id: format!("feed:{}", entry.id),
+    })
+}
+
+#[test]
+fn testParseFeed()
+{
+    let posts = FeedContent::new("test", "").unwrap()
+        .parse(include_bytes!("../test-data/feed.xml")).unwrap();
+    // The entry without images is skipped.
+    assert_eq!(posts.len(), 2);
+    assert_eq!(posts[0].title, "Group buy: GMK Olivia");
+    assert_eq!(posts[0].media, reddit::PostMedia::Image(
+        "https://example.com/olivia.jpg".to_owned()));
+    assert_eq!(posts[0].url, "https://example.com/olivia");
+    assert_eq!(posts[1].media, reddit::PostMedia::Image(
+        "https://example.com/build.png".to_owned()));
+}
+
+impl ContentSource for FeedContent
+{
+    fn name(&self) -> String
+    {
+        self.name.clone()
+    }
+
+    fn fetch<'a>(&'a self, since: DateTime<Utc>)
+                 -> BoxFuture<'a, Result<Vec<MediaPost>, Error>>
+    {
+        async move {
+            debug!("Getting posts from feed {}...", self.url);
+            let res = reqwest::get(&self.url).await.and_then(
+                |res| res.error_for_status()).map_err(
+                |e| error!(RuntimeError, format!("Failed to get feed {}: {}",
+                                                 self.url, e)))?;
+            let body = res.bytes().await.map_err(
+                |e| error!(RuntimeError, format!("Failed to read feed {}: {}",
+                                                 self.url, e)))?;
+            let mut posts = self.parse(&body)?;
+            posts.retain(|p| p.time_create >= since);
+            Ok(posts)
+        }.boxed()
+    }
+}
+
+/// Images in a local folder. Every image is a candidate, in random
+/// order, until it is sent.
+pub struct FolderContent
+{
+    name: String,
+    path: String,
+}
+
+impl FolderContent
+{
+    pub fn new(name: &str, path: &str) -> Self
+    {
+        Self { name: name.to_owned(), path: path.to_owned() }
+    }
+}
+
+fn isImageFile(path: &Path) -> bool
+{
+    match path.extension().and_then(|e| e.to_str())
+    {
+        Some(ext) => ["jpg", "jpeg", "png", "webp"].iter()
+            .any(|e| e.eq_ignore_ascii_case(ext)),
+        None => false,
+    }
+}
+
+impl ContentSource for FolderContent
+{
+    fn name(&self) -> String
+    {
+        self.name.clone()
+    }
+
+    fn fetch<'a>(&'a self, _since: DateTime<Utc>)
+                 -> BoxFuture<'a, Result<Vec<MediaPost>, Error>>
+    {
+        async move {
+            let entries = fs::read_dir(&self.path).map_err(
+                |e| error!(RuntimeError, format!("Failed to read folder {}: {}",
+                                                 self.path, e)))?;
+            let mut posts = Vec::new();
+            for entry in entries.filter_map(|e| e.ok())
+            {
+                let path = entry.path();
+                if !isImageFile(&path)
+                {
+                    continue;
+                }
+                let uri = match path.to_str()
+                {
+                    Some(uri) => uri.to_owned(),
+                    None => continue,
+                };
+                let time = entry.metadata().and_then(|m| m.modified())
+                    .map(DateTime::<Utc>::from).unwrap_or_else(|_| Utc::now());
+                posts.push(MediaPost {
+                    id: format!("file:{}", uri),
+                    origin_id: None,
+                    title: path.file_stem().and_then(|s| s.to_str())
+                        .unwrap_or("").to_owned(),
+                    author: String::new(),
+                    author_fullname: None,
+                    score: 1,
+                    count_comments: 0,
+                    upvote_ratio: 1.0,
+                    flair: None,
+                    over_18: false,
+                    spoiler: false,
+                    media: reddit::PostMedia::Image(uri),
+                    url: String::new(),
+                    time_create: time,
+                    community: self.name.clone(),
+                });
+            }
+            posts.shuffle(&mut thread_rng());
+            Ok(posts)
+        }.boxed()
+    }
+}
+
+/// Make the source in the config.
+pub fn fromConfig(config: &bot_config::ContentSourceConfig)
+                  -> Result<Box<dyn ContentSource>, Error>
+{
+    let source: Box<dyn ContentSource> = match config.kind
+    {
+        bot_config::ContentKind::Feed =>
+            Box::new(FeedContent::new(&config.name, &config.location)?),
+        bot_config::ContentKind::Folder =>
+            Box::new(FolderContent::new(&config.name, &config.location)),
+    };
+    Ok(source)
+}
@@ -0,0 +1,159 @@
+use crate::error::Error;
+use crate::utils;
+use crate::bot_config;
+
+/// Return the languages to try for the language code `lang`, the
+/// most specific first. E.g. "zh-hans" gives ["zh-hans", "zh"].
+fn languageChain(lang: &str) -> Vec<String>
+{
+    let lang = lang.to_lowercase().replace('_', "-");
+    let mut result = vec![lang.clone()];
+    let mut rest: &str = &lang;
+    while let Some(idx) = rest.rfind('-')
+    {
+        rest = &rest[..idx];
+        result.push(rest.to_owned());
+    }
+    result
+}
+
+#[test]
+fn testLanguageChain()
+{
+    assert_eq!(languageChain("zh-Hans-CN"), vec!["zh-hans-cn", "zh-hans", "zh"]);
+    assert_eq!(languageChain("en"), vec!["en"]);
+}
+
+/// Return the template `key` in the config without any translation.
+/// The key is the name of the template in the config.
+fn baseTemplate<'a>(config: &'a bot_config::ConfigParams, key: &str)
+                    -> Option<&'a str>
+{
+    let tplt = match key
+    {
+        "welcome" => &config.general.welcome,
+        "welcome_batch" => &config.general.welcome_batch,
+        "farewell" => &config.general.farewell,
+        "invalid_days_template" => &config.general.invalid_days_template,
+        "weekly_waer_template" => &config.general.weekly_waer_template,
+        "weekly_waable_template" => &config.general.weekly_waable_template,
+        "monthly_waer_template" => &config.general.monthly_waer_template,
+        "monthly_waable_template" => &config.general.monthly_waable_template,
+        "monthly_members_template" => &config.general.monthly_members_template,
+        "flagged_template" => &config.wa_limit.flagged_template,
+        "hall_of_fame_template" => &config.hall_of_fame.as_ref()?.template,
+        "daily_pic_caption" => &config.reddit.daily_pic_caption,
+        "bestpics_template" => &config.reddit.bestpics_template,
+        "bestpics_item_template" => &config.reddit.bestpics_item_template,
+        "monthly_bestpics_template" => &config.reddit.monthly_bestpics_template,
+        "reddit_weekly_template" => &config.reddit.weekly_template,
+        "reddit_weekly_item_template" => &config.reddit.weekly_item_template,
+        "captcha_prompt" => &config.captcha.as_ref()?.prompt,
+        "captcha_not_yours" => &config.captcha.as_ref()?.not_yours,
+        "captcha_wrong_answer" => &config.captcha.as_ref()?.wrong_answer,
+        "captcha_right_answer" => &config.captcha.as_ref()?.right_answer,
+        "vote_question" => &config.reddit.vote.as_ref()?.question,
+        "vote_stats_template" => &config.reddit.vote.as_ref()?.stats_template,
+        "watch_template" => &config.reddit.watch.as_ref()?.template,
+        "watch_subscribed_template" =>
+            &config.reddit.watch.as_ref()?.subscribed_template,
+        "watch_unsubscribed_template" =>
+            &config.reddit.watch.as_ref()?.unsubscribed_template,
+        _ => return None,
+    };
+    Some(tplt)
+}
+
+/// Return the default language of group-wide messages in the chat.
+pub fn chatLanguage(config: &bot_config::ConfigParams, chat_id: i64)
+                    -> Option<&str>
+{
+    config.i18n.chat_languages.get(&chat_id.to_string())
+        .or(config.i18n.default_language.as_ref()).map(|s| s.as_str())
+}
+
+/// Look up the template `key` in the message catalogs. Try the
+/// language `lang` (usually the language of a user) first, then the
+/// language of the chat, then the fallback languages, and at last use
+/// the template in its section of the config, e.g. `[general]`.
+pub fn lookup<'a>(config: &'a bot_config::ConfigParams, key: &str,
+                  lang: Option<&str>, chat_id: Option<i64>)
+                  -> Result<&'a str, Error>
+{
+    let mut langs: Vec<String> = Vec::new();
+    if let Some(l) = lang
+    {
+        langs.append(&mut languageChain(l));
+    }
+    if let Some(l) = chat_id.and_then(|id| chatLanguage(config, id))
+    {
+        langs.append(&mut languageChain(l));
+    }
+    for l in &config.i18n.fallback
+    {
+        langs.append(&mut languageChain(l));
+    }
+
+    for l in langs
+    {
+        if let Some(tplt) = config.i18n.catalogs.get(&l)
+            .and_then(|catalog| catalog.get(key))
+        {
+            return Ok(tplt);
+        }
+    }
+    baseTemplate(config, key).ok_or_else(
+        || error!(RuntimeError, format!("Unknown template: {}", key)))
+}
+
+#[test]
+fn testLookup()
+{
+    let config: bot_config::ConfigParams = toml::from_str(r#"
+        [general]
+        do_welcome = true
+        welcome = "base"
+        token = ""
+        username = ""
+        weekly_waer_template = ""
+        weekly_waable_template = ""
+        monthly_waer_template = ""
+        monthly_waable_template = ""
+
+        [reddit]
+        client_id = ""
+        client_secret = ""
+        daily_pic_caption = ""
+
+        [i18n]
+        default_language = "fr"
+        fallback = ["en"]
+
+        [i18n.chat_languages]
+        "-100" = "de"
+
+        [i18n.catalogs.zh]
+        welcome = "zh"
+        [i18n.catalogs.de]
+        welcome = "de"
+        [i18n.catalogs.en]
+        welcome = "en"
+    "#).unwrap();
+    // The language of the user comes first, then of the chat.
+    assert_eq!(lookup(&config, "welcome", Some("zh-Hans"), Some(-100)).unwrap(),
+               "zh");
+    assert_eq!(lookup(&config, "welcome", Some("ja"), Some(-100)).unwrap(), "de");
+    // Chats not in chat_languages use default_language, which has no
+    // catalog here, so the fallback is used.
+    assert_eq!(lookup(&config, "welcome", None, Some(-200)).unwrap(), "en");
+    assert_eq!(lookup(&config, "farewell", Some("zh"), Some(-100)).unwrap(), "");
+    assert!(lookup(&config, "no_such_template", None, None).is_err());
+}
+
+/// Same as `lookup()`, but return a template.
+pub fn template(config: &bot_config::ConfigParams, key: &str,
+                lang: Option<&str>, chat_id: Option<i64>)
+                -> Result<utils::SimpleTemplate, Error>
+{
+    lookup(config, key, lang, chat_id).map(utils::SimpleTemplate::new)
+}
@@ -1,5 +1,7 @@
 use std::str;
 use std::fs;
+use std::time;
+use std::collections::BTreeMap;
 use std::io::prelude::*;
 
 use rand::prelude::*;
@@ -21,13 +23,40 @@ use crate::reddit;
 use crate::bot_config;
 use crate::telegram;
 use crate::chat_db;
+use crate::captcha;
+use crate::reddit_watch;
+use crate::i18n;
+use crate::content_source;
+use crate::content_source::MediaPost;
+
+/// Number of pictures listed by /bestpics and the monthly report.
+const BEST_PICS_COUNT: u32 = 5;
+/// Most days a command can look back, e.g. /bestpics.
+const MAX_COMMAND_DAYS: i64 = 3650;
+/// Number of pages to read from a listing for the daily picture.
+const DAILY_MAX_PAGES: u32 = 2;
+/// Number of pages to read from a listing for the weekly album.
+const WEEKLY_MAX_PAGES: u32 = 3;
+/// The weekly album is only sent with at least this many pictures.
+const WEEKLY_MIN_COUNT: usize = 5;
+/// How often the bot checks for votes to close.
+const VOTE_CHECK_INTERVAL: time::Duration = time::Duration::from_secs(60);
+
+/// The last daily picture in a chat, and the wa-s to it so far.
+#[derive(Serialize, Deserialize)]
+struct LastDailyPic
+{
+    msg_id: i64,
+    wa_count: u32,
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct RuntimeInfo
 {
     chat_id: Vec<i64>,
-    last_msg_id: Option<i64>,
-    wa_count: u32,
+    /// The last daily picture, keyed by chat ID.
+    #[serde(default)]
+    last_daily_pics: BTreeMap<i64, LastDailyPic>,
 }
 
 impl RuntimeInfo
@@ -38,8 +67,7 @@ impl RuntimeInfo
     {
         Self {
             chat_id: Vec::new(),
-            last_msg_id: None,
-            wa_count: 0,
+            last_daily_pics: BTreeMap::new(),
         }
     }
 
@@ -76,83 +104,360 @@ impl RuntimeInfo
     }
 }
 
+/// Welcome `new_users` in one message, with the `welcome` template for
+/// one member, and `welcome_batch` for more.
 async fn welcome(api: &bot::Api, config: &bot_config::ConfigParams,
-                 new_users: &Vec<bot::User>, chat: &bot::Channel)
-                 -> Result<(), Error>
+                 new_users: &[bot::User], chat_id: i64) -> Result<(), Error>
 {
-    if config.general.welcome.is_empty()
+    let users: Vec<String> = new_users.iter().map(
+        |user| telegram::mention(user, config.general.mention_style,
+                                 config.general.text_format)).collect();
+    // Use the language of the new members if they all speak the same,
+    // otherwise the language of the chat.
+    let lang = new_users.first().and_then(|u| u.language_code.as_deref())
+        .filter(|l| new_users.iter().all(
+            |u| u.language_code.as_deref() == Some(*l)));
+    let key = if users.len() == 1 { "welcome" } else { "welcome_batch" };
+    // The template may only be in a catalog.
+    let tplt = i18n::lookup(config, key, lang, Some(chat_id))?;
+    if tplt.is_empty()
     {
         return Err(error!(RuntimeError, "No welcome message set"));
     }
+    let msg = utils::SimpleTemplate::new(tplt)
+        .apply("user", users.first().map(|u| u.as_str()).unwrap_or(""))
+        .apply("users", users.join(&config.general.welcome_separator))
+        .result();
+    let post = telegram::sendFormatted(
+        api, bot::types::ChatId::new(chat_id), &msg, config.general.text_format,
+        None).await.map_err(|_| error!(RuntimeError, "Failed to welcome"))?;
 
-    let mut err = false;
-    for user in new_users
+    if let Some(lifetime) = config.general.welcome_lifetime_sec
+    {
+        deleteLater(api, chat_id, i64::from(telegram::getPostId(&post)),
+                    chrono::Utc::now() + chrono::Duration::seconds(lifetime as i64))?;
+    }
+    Ok(())
+}
+
+/// Welcome `new_users`, together with the other members joining the
+/// chat within `welcome_batch_sec`.
+pub async fn welcomeInBatch(api: &bot::Api, config: &bot_config::ConfigParams,
+                            new_users: &[bot::User], chat_id: i64)
+                            -> Result<(), Error>
+{
+    let window = config.general.welcome_batch_sec;
+    if window == 0
+    {
+        return welcome(api, config, new_users, chat_id).await;
+    }
+
+    // The waiting members are kept in the database, so that they are
+    // still welcomed if the bot restarts, see `resumeWelcomes()`.
+    let users: Vec<(i64, String)> = new_users.iter().map(
+        |u| (i64::from(u.id), userToJson(u))).collect();
+    let due = chrono::Utc::now() + chrono::Duration::seconds(window as i64);
+    // Whoever comes first in the window sends the welcome.
+    if !chat_db::addPendingWelcomes(chat_id, &users, due)?
+    {
+        return Ok(());
+    }
+    welcomePendingAt(api, config, chat_id, due).await
+}
+
+/// The fields of `user` needed to welcome it, in JSON.
+fn userToJson(user: &bot::User) -> String
+{
+    serde_json::json!({
+        "id": i64::from(user.id),
+        "is_bot": user.is_bot,
+        "first_name": user.first_name,
+        "last_name": user.last_name,
+        "username": user.username,
+        "language_code": user.language_code,
+    }).to_string()
+}
+
+/// Welcome the members waiting in the chat at `due`.
+async fn welcomePendingAt(api: &bot::Api, config: &bot_config::ConfigParams,
+                          chat_id: i64, due: chrono::DateTime<chrono::Utc>)
+                          -> Result<(), Error>
+{
+    let wait = (due - chrono::Utc::now()).to_std()
+        .unwrap_or(time::Duration::from_secs(0));
+    tokio::time::delay_for(wait).await;
+    let mut users: Vec<bot::User> = Vec::new();
+    for user in chat_db::takePendingWelcomes(chat_id)?
     {
-        let name = telegram::getUsername(user);
-        info!("{} joined chat {} ({}).", name, chat.id, chat.title);
-        let msg_tplt = utils::SimpleTemplate::new(&config.general.welcome);
-        let msg = msg_tplt
-            .apply("user", format!("tg://user?id={}",
-                                   telegram::getUserFullname(user)))
-            .result();
-        if api.send(SendMessage::new(chat.id, msg)).await.is_err()
+        match serde_json::from_str(&user)
         {
-            err = true;
+            Ok(user) => users.push(user),
+            Err(e) => log_error!("Invalid pending welcome {}: {}", user, e),
         }
     }
-    if err
+    if users.is_empty()
     {
-        Err(error!(RuntimeError, "Failed to welcome"))
+        return Ok(());
     }
-    else
+    welcome(api, config, &users, chat_id).await
+}
+
+/// Welcome the members who were waiting when the bot stopped.
+fn resumeWelcomes(api: &bot::Api, config: &bot_config::ConfigParams)
+                  -> Result<(), Error>
+{
+    for (chat_id, due) in chat_db::pendingWelcomeChats()?
     {
-        Ok(())
+        let api = api.clone();
+        let config = config.clone();
+        tokio::spawn(async move {
+            if let Err(e) = welcomePendingAt(&api, &config, chat_id, due).await
+            {
+                log_error!("{}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Delete the message at `due`. This survives restarts, see
+/// `resumeDeletions()`.
+fn deleteLater(api: &bot::Api, chat_id: i64, msg_id: i64,
+               due: chrono::DateTime<chrono::Utc>) -> Result<(), Error>
+{
+    chat_db::addDeletion(chat_id, msg_id, due)?;
+    let api = api.clone();
+    tokio::spawn(async move {
+        let wait = (due - chrono::Utc::now()).to_std()
+            .unwrap_or(time::Duration::from_secs(0));
+        tokio::time::delay_for(wait).await;
+        if let Err(e) = telegram::deleteMessage(
+            &api, bot::types::ChatId::new(chat_id),
+            bot::types::MessageId::new(msg_id)).await
+        {
+            log_error!("{}", e);
+        }
+        if let Err(e) = chat_db::removeDeletion(chat_id, msg_id)
+        {
+            log_error!("{}", e);
+        }
+    });
+    Ok(())
+}
+
+/// Restart the scheduled deletions, after the bot is restarted.
+fn resumeDeletions(api: &bot::Api) -> Result<(), Error>
+{
+    for (chat_id, msg_id, due) in chat_db::pendingDeletions()?
+    {
+        deleteLater(api, chat_id, msg_id, due)?;
+    }
+    Ok(())
+}
+
+/// The chat where a member event happens.
+struct ChatInfo
+{
+    id: i64,
+    title: String,
+}
+
+/// Is `user` this bot?
+fn isSelf(config: &bot_config::ConfigParams, user: &bot::User) -> bool
+{
+    match &user.username
+    {
+        Some(nick) => nick == &config.general.username,
+        None => false,
+    }
+}
+
+fn memberEntry(chat_id: i64, user: &bot::User, added_by: Option<&bot::User>)
+               -> chat_db::MemberEntry
+{
+    chat_db::MemberEntry {
+        chat_id: chat_id,
+        user_id: i64::from(user.id),
+        first_name: user.first_name.clone(),
+        last_name: user.last_name.clone(),
+        username: user.username.clone(),
+        time: chrono::Utc::now(),
+        added_by: added_by.filter(|u| u.id != user.id).map(|u| i64::from(u.id)),
     }
 }
 
 async fn onNewChatMembers(api: &bot::Api, config: &bot_config::ConfigParams,
-                          new_users: &Vec<bot::User>, chat: &bot::Channel)
-                          -> Result<(), Error>
+                          new_users: &Vec<bot::User>, chat: &ChatInfo,
+                          added_by: Option<&bot::User>) -> Result<(), Error>
 {
     debug!("New members in chat {} ({}).", chat.id, chat.title);
-    // Is the bot one of the new members?
-    if new_users.iter().any(|u|
-        match &u.username
+    for user in new_users
+    {
+        info!("{} joined chat {} ({}).", telegram::getUsername(user), chat.id,
+              chat.title);
+        if let Err(e) = chat_db::addMemberJoin(memberEntry(chat.id, user, added_by))
         {
-            Some(nick) => nick == &config.general.username,
-            None => false,
-        })
+            log_error!("{}", e);
+        }
+    }
+    // Is the bot one of the new members?
+    if new_users.iter().any(|u| isSelf(config, u))
     {
         let mut info = RuntimeInfo::load()?;
-        if !info.chat_id.contains(&(i64::from(chat.id)))
+        if !info.chat_id.contains(&chat.id)
         {
-            info.chat_id.push(i64::from(chat.id));
+            info.chat_id.push(chat.id);
             if let Err(e) = info.save()
             {
                 log_error!("{}", e);
             }
         }
     }
+    else if let Some(captcha_config) = &config.captcha
+    {
+        // Members who pass the captcha are welcomed then.
+        for user in new_users.iter().filter(|u| !u.is_bot)
+        {
+            if let Err(e) = captcha::challenge(api, config, captcha_config,
+                                               chat.id, user).await
+            {
+                log_error!("Failed to challenge {}: {}",
+                           telegram::getUsername(user), e);
+            }
+        }
+    }
     else if config.general.do_welcome
     {
-        welcome(api, config, new_users, chat).await?;
+        welcomeInBatch(api, config, new_users, chat.id).await?;
     }
     Ok(())
 }
 
-async fn onWaReply(api: &bot::Api, config: &bot_config::ConfigParams, msg: &Message)
-                   -> Result<(), Error>
+/// Handle a member leaving the chat. `removed_by` is who removed the
+/// member, if known.
+async fn onLeftChatMember(api: &bot::Api, config: &bot_config::ConfigParams,
+                          user: &bot::User, chat: &ChatInfo,
+                          removed_by: Option<&bot::User>) -> Result<(), Error>
 {
-    let chat_id = msg.chat.id();
-    // Sliently ignore if the reply is not sent in the correct chat.
-    if let Some(correct_chat) = config.general.group_id
+    info!("{} left chat {} ({}).", telegram::getUsername(user), chat.id,
+          chat.title);
+    if isSelf(config, user)
+    {
+        let mut info = RuntimeInfo::load()?;
+        info.chat_id.retain(|id| *id != chat.id);
+        return info.save();
+    }
+    if let Err(e) = chat_db::addMemberLeave(memberEntry(chat.id, user, None))
+    {
+        log_error!("{}", e);
+    }
+
+    // Nobody is left to answer the captcha.
+    let captcha = chat_db::takeCaptcha(chat.id, i64::from(user.id))?;
+    if let Some(captcha) = &captcha
     {
-        if chat_id != bot::types::ChatId::new(correct_chat)
+        if let Err(e) = telegram::deleteMessage(
+            api, bot::types::ChatId::new(chat.id),
+            bot::types::MessageId::new(captcha.msg_id)).await
         {
-            return Ok(());
+            log_error!("{}", e);
         }
     }
-    else
+
+    // No farewell to members who never passed the captcha, or who
+    // are kicked by the bot, e.g. for failing it.
+    let kicked_by_self = removed_by.map(|u| isSelf(config, u)).unwrap_or(false);
+    if config.general.do_farewell && captcha.is_none() && !kicked_by_self
+    {
+        if let Err(e) = sendFarewell(api, config, user, chat.id).await
+        {
+            log_error!("Failed to send farewell: {}", e);
+        }
+    }
+    Ok(())
+}
+
+async fn sendFarewell(api: &bot::Api, config: &bot_config::ConfigParams,
+                      user: &bot::User, chat_id: i64) -> Result<(), Error>
+{
+    let tplt = i18n::lookup(config, "farewell", user.language_code.as_deref(),
+                            Some(chat_id))?;
+    if tplt.is_empty()
+    {
+        return Ok(());
+    }
+    let msg = utils::SimpleTemplate::new(tplt)
+        .apply("user", telegram::mention(user, config.general.mention_style,
+                                         config.general.text_format))
+        .result();
+    telegram::sendFormatted(api, bot::types::ChatId::new(chat_id), &msg,
+                            config.general.text_format, None).await?;
+    Ok(())
+}
+
+/// Handle member lifecycle events. These come as either messages or
+/// channel posts, depending on the type of the chat. Return false if
+/// `kind` is not a member event.
+async fn onMemberEvent(api: &bot::Api, config: &bot_config::ConfigParams,
+                       kind: &MessageKind, chat: &ChatInfo,
+                       from: Option<&bot::User>) -> Result<bool, Error>
+{
+    match kind
+    {
+        MessageKind::NewChatMembers{ref data} =>
+        {
+            onNewChatMembers(api, config, data, chat, from).await?;
+        },
+        MessageKind::LeftChatMember{ref data} =>
+        {
+            onLeftChatMember(api, config, data, chat, from).await?;
+        },
+        _ => { return Ok(false); },
+    }
+    Ok(true)
+}
+
+/// Check `wa` against the wa limits in the config. Return the reason
+/// if it is over the limits.
+fn checkWaLimit(config: &bot_config::ConfigParams, wa: &chat_db::WaEntry)
+                -> Result<Option<String>, Error>
+{
+    if let Some(gap) = config.wa_limit.min_gap_sec
+    {
+        if let Some(last) = chat_db::lastWaTime(wa.chat_id, wa.waer)?
+        {
+            if wa.time - last < chrono::Duration::seconds(gap)
+            {
+                return Ok(Some(format!("less than {}s after the last wa", gap)));
+            }
+        }
+    }
+    if let Some(max) = config.wa_limit.max_per_hour
+    {
+        let since = wa.time - chrono::Duration::hours(1);
+        if chat_db::waCountSince(wa.chat_id, wa.waer, since)? >= max
+        {
+            return Ok(Some(format!("more than {} was in an hour", max)));
+        }
+    }
+    Ok(None)
+}
+
+/// Are the wa-s in the chat counted? They are in the group, and in the
+/// chats that daily pictures are sent to.
+fn isWaChat(config: &bot_config::ConfigParams, chat_id: i64) -> bool
+{
+    config.general.group_id == Some(chat_id) ||
+        config.reddit.sources.iter().any(|s| s.chat_id == Some(chat_id)) ||
+        config.content_sources.iter().any(|s| s.chat_id == Some(chat_id))
+}
+
+async fn onWaReply(api: &bot::Api, config: &bot_config::ConfigParams, msg: &Message)
+                   -> Result<(), Error>
+{
+    let chat_id = msg.chat.id();
+    // Sliently ignore if the reply is not sent in the correct chat.
+    if !isWaChat(config, i64::from(chat_id))
     {
         return Ok(());
     }
@@ -161,14 +466,45 @@ async fn onWaReply(api: &bot::Api, config: &bot_config::ConfigParams, msg: &Mess
     let waable_id = telegram::getParentMsgId(msg)
         .ok_or_else(|| error!(RuntimeError, "Wa is not a reply"))?;
 
-    let wa_count = chat_db::addWa(chat_db::WaEntry {
+    let wa = chat_db::WaEntry {
+        chat_id: i64::from(chat_id),
         wa_to: i64::from(waable_id),
         id: i64::from(msg.id),
         waer: i64::from(msg.from.id),
         time: chrono::Utc.timestamp(msg.date, 0),
-    })?;
+    };
+    if let Some(reason) = checkWaLimit(config, &wa)?
+    {
+        info!("Wa from {} is flagged: {}.", telegram::getUsername(&msg.from),
+              reason);
+        return chat_db::addFlaggedWa(wa, &reason);
+    }
+    let wa_count = chat_db::addWa(wa)?;
 
     debug!("It's a wa. Wa count is {}", wa_count);
+    let mut info = RuntimeInfo::load()?;
+    if let Some(last) = info.last_daily_pics.get_mut(&i64::from(chat_id))
+    {
+        if last.msg_id == i64::from(waable_id)
+        {
+            last.wa_count += 1;
+            info.save()?;
+        }
+    }
+
+    // Only messages in the group get into the hall of fame.
+    if let Some(hall) = &config.hall_of_fame
+    {
+        if wa_count >= hall.threshold &&
+            config.general.group_id == Some(i64::from(chat_id))
+        {
+            if let Err(e) = sendToHallOfFame(api, config, hall, msg, wa_count).await
+            {
+                log_error!("{}", e);
+            }
+        }
+    }
+
     if wa_count == 3
     {
         let delay: f64 = thread_rng().gen_range(10.0, 600.0);
@@ -178,6 +514,48 @@ async fn onWaReply(api: &bot::Api, config: &bot_config::ConfigParams, msg: &Mess
     Ok(())
 }
 
+/// Forward the message that `wa_msg` replies to into the hall of fame
+/// channel, unless it is already there.
+async fn sendToHallOfFame(api: &bot::Api, config: &bot_config::ConfigParams,
+                          hall: &bot_config::ConfigParamsHallOfFame,
+                          wa_msg: &Message, wa_count: u32) -> Result<(), Error>
+{
+    let waable = telegram::getParentMsg(wa_msg)
+        .ok_or_else(|| error!(RuntimeError, "Wa is not a reply"))?;
+    let waable_id = i64::from(waable.id);
+    let chat_id = waable.chat.id();
+    if !chat_db::claimHallOfFame(i64::from(chat_id), waable_id,
+                                 i64::from(waable.from.id), chrono::Utc::now())?
+    {
+        return Ok(());
+    }
+
+    info!("Sending message {} to the hall of fame.", waable_id);
+    let channel = bot::types::ChatId::new(hall.channel_id);
+    let forwarded_id = match telegram::forwardMessage(
+        api, waable.id, chat_id, channel).await
+    {
+        Ok(id) => id,
+        Err(e) =>
+        {
+            chat_db::releaseHallOfFame(i64::from(chat_id), waable_id)?;
+            return Err(e);
+        },
+    };
+    chat_db::setHallOfFameForward(i64::from(chat_id), waable_id,
+                                  i64::from(forwarded_id))?;
+
+    let text = i18n::template(config, "hall_of_fame_template", None,
+                              Some(i64::from(chat_id)))?
+        .apply("author", telegram::getUserFullname(&waable.from))
+        .apply("count", wa_count)
+        .apply("link", telegram::getMsgLink(i64::from(chat_id), waable_id))
+        .result();
+    api.send(SendMessage::new(channel, text).reply_to(forwarded_id)).await
+        .map_err(|_| error!(RuntimeError, "Failed to credit hall of fame author"))?;
+    Ok(())
+}
+
 async fn onTextReplyToMsg(api: &bot::Api, config: &bot_config::ConfigParams,
                           msg: &Message, reply_to: &Message) -> Result<(), Error>
 {
@@ -208,151 +586,1284 @@ async fn onTextReply(api: &bot::Api, config: &bot_config::ConfigParams,
     Ok(())
 }
 
-async fn trySendFirstPhotoFromPosts(api: &bot::Api, chat_id: i64,
-                              reddit_posts: &Vec<&reddit::Post>,
-                              caption_tplt: &str) -> Result<Message, Error>
+/// A source of the daily picture, and how its posts are used.
+struct DailySource<'a>
+{
+    content: Box<dyn content_source::ContentSource + 'a>,
+    /// Rank scores of the posts are multiplied by this.
+    weight: f64,
+    caption: Option<&'a str>,
+    chat_id: i64,
+    /// Whether the posts are from Reddit. Only Reddit posts have
+    /// scores and flairs, so only they are ranked and filtered.
+    is_reddit: bool,
+}
+
+/// A candidate post for the daily picture, and how it is used.
+struct Candidate<'a>
+{
+    post: &'a MediaPost,
+    weight: f64,
+    caption: Option<&'a str>,
+    is_reddit: bool,
+}
+
+/// Score of the post under the ranking, at time `now`.
+fn rankPost(config: &bot_config::ConfigParamsReddit, post: &MediaPost,
+            now: &DateTime<Utc>) -> f64
 {
-    for best_post in reddit_posts
+    let score = post.score as f64;
+    match config.ranking
     {
-        info!("Best reddit post today is {}, with image at {}.",
-              best_post.shortUrl(), &best_post.link);
+        bot_config::Ranking::Score => score,
+        bot_config::Ranking::ScoreRatio => score * post.upvote_ratio,
+        bot_config::Ranking::Comments =>
+            score + config.comment_weight * post.count_comments as f64,
+        bot_config::Ranking::ScorePerHour =>
+        {
+            let hours = (*now - post.time_create).num_seconds() as f64 / 3600.0;
+            // Do not let a post from a minute ago win with a few votes.
+            score / hours.max(1.0)
+        },
+    }
+}
+
+#[test]
+fn testRankPost()
+{
+    let now = Utc.timestamp(1600000000, 0);
+    let mut config: bot_config::ConfigParamsReddit = toml::from_str(
+        "client_id = \"\"\nclient_secret = \"\"\ndaily_pic_caption = \"\"")
+        .unwrap();
+    let mut post = MediaPost::from(reddit::Post::new());
+    post.score = 100;
+    post.upvote_ratio = 0.5;
+    post.count_comments = 10;
+    post.time_create = now - chrono::Duration::hours(4);
+    assert_eq!(rankPost(&config, &post, &now), 100.0);
+    config.ranking = bot_config::Ranking::ScoreRatio;
+    assert_eq!(rankPost(&config, &post, &now), 50.0);
+    config.ranking = bot_config::Ranking::Comments;
+    config.comment_weight = 2.0;
+    assert_eq!(rankPost(&config, &post, &now), 120.0);
+    config.ranking = bot_config::Ranking::ScorePerHour;
+    assert_eq!(rankPost(&config, &post, &now), 25.0);
+    post.time_create = now;
+    assert_eq!(rankPost(&config, &post, &now), 100.0);
+}
 
-        if let Ok(msg) =
-            telegram::sendPhoto(
-                api, &best_post.link, &utils::SimpleTemplate::new(caption_tplt)
-                    .apply("url", best_post.shortUrl()).result(),
-                chat_id).await
+impl<'a> Candidate<'a>
+{
+    /// Score to rank the candidates from different sources. Posts
+    /// not from Reddit have no score, so they rank with the weight of
+    /// their source.
+    fn rankScore(&self, config: &bot_config::ConfigParamsReddit,
+                 now: &DateTime<Utc>) -> f64
+    {
+        if self.is_reddit
         {
-            return Ok(msg);
+            rankPost(config, self.post, now) * self.weight
         }
         else
         {
-            log_error!("Failed to send post {} as the best daily post.",
-                       best_post.url);
+            self.weight
         }
     }
-    Err(error!(RuntimeError, "Failed to send best post"))
 }
 
-async fn getRedditPostsToday(config: &bot_config::ConfigParams)
-                             -> Result<Vec<reddit::Post>, Error>
+#[test]
+fn testRankScore()
 {
-    // API rule says must authenticate.
-    // https://github.com/reddit-archive/reddit/wiki/API
-    debug!("Authenticating on Reddit...");
-    let redditor = reddit::RedditQuerier::fromUserlessAuthentication(
-        &config.reddit.client_id, &config.reddit.client_secret).await?;
+    let now = Utc.timestamp(1600000000, 0);
+    let mut config: bot_config::ConfigParamsReddit = toml::from_str(
+        "client_id = \"\"\nclient_secret = \"\"\ndaily_pic_caption = \"\"")
+        .unwrap();
+    config.ranking = bot_config::Ranking::ScorePerHour;
+    let mut post = MediaPost::from(reddit::Post::new());
+    post.score = 100;
+    post.time_create = now - chrono::Duration::hours(4);
+    let mut candidate = Candidate {
+        post: &post,
+        weight: 2.0,
+        caption: None,
+        is_reddit: true,
+    };
+    assert_eq!(candidate.rankScore(&config, &now), 50.0);
+    candidate.is_reddit = false;
+    assert_eq!(candidate.rankScore(&config, &now), 2.0);
+}
 
-    let sub = reddit::Subreddit::new("MechanicalKeyboards");
-    debug!("Getting posts...");
-    let mut posts = sub.list(&redditor, reddit::PostSorting::new, None, None)
-        .await?;
-    let now = chrono::offset::Utc::now();
-    let yesterday = now - chrono::Duration::days(1);
+fn renderCaption(tplt: &str, post: &MediaPost, title: &str,
+                 format: bot_config::TextFormat) -> String
+{
+    let esc = |text: &str| telegram::escape(text, format);
+    utils::SimpleTemplate::new(tplt).applyAll(&[
+        ("url", esc(&post.url)),
+        ("title", esc(title)),
+        ("author", esc(&post.author)),
+        ("score", post.score.to_string()),
+        ("count_comments", post.count_comments.to_string()),
+        ("subreddit", esc(&post.community)),
+        ("flair", esc(post.flair.as_deref().unwrap_or(""))),
+        ("time", post.time_create.format("%Y-%m-%d %H:%M UTC").to_string()),
+    ]).result()
+}
 
+/// Make the caption of the daily picture from the template, with the
+/// values escaped for `format`. The title is shortened if the caption
+/// is too long for Telegram, which counts UTF-16 code units. Fail if
+/// the caption is too long even without the title.
+fn dailyPicCaption(tplt: &str, post: &MediaPost, format: bot_config::TextFormat)
+                   -> Result<String, Error>
+{
+    let limit = bot_config::TG_CAPTION_LIMIT;
+    let mut title: Vec<char> = post.title.chars().collect();
+    let mut title_str = post.title.clone();
+    loop
     {
-        while posts.last().unwrap().time_create > yesterday
+        let caption = renderCaption(tplt, post, &title_str, format);
+        let length = caption.encode_utf16().count();
+        if length <= limit
+        {
+            return Ok(caption);
+        }
+        if title.is_empty()
         {
-            let last_fullname: String = posts.last().unwrap().fullName()
-                .to_owned();
-            posts.append(&mut sub.list(&redditor, reddit::PostSorting::new, None,
-                                       Some(&last_fullname)).await?);
+            return Err(error!(RuntimeError, format!(
+                "Caption of post {} is too long without the title ({} > {})",
+                post.id, length, limit)));
         }
+        let keep = title.len().saturating_sub(length - limit + 1);
+        title.truncate(keep);
+        title_str = title.iter().collect::<String>() + "…";
     }
+}
 
-    for i in (0..=posts.len()-1).rev()
+#[test]
+fn testDailyPicCaption()
+{
+    let mut post = MediaPost::from(reddit::Post::new());
+    post.title = "<GMK> & $1".to_owned();
+    post.author = "someone".to_owned();
+    post.score = 42;
+    post.community = "MechanicalKeyboards".to_owned();
+    let tplt = "<b>${title}</b> by ${author} (${score}) in r/${subreddit}";
+    assert_eq!(dailyPicCaption(tplt, &post, bot_config::TextFormat::Html).unwrap(),
+               "<b>&lt;GMK&gt; &amp; $1</b> by someone (42) in r/MechanicalKeyboards");
+    post.title = "${author}".to_owned();
+    assert_eq!(dailyPicCaption(tplt, &post, bot_config::TextFormat::Html).unwrap(),
+               "<b>${author}</b> by someone (42) in r/MechanicalKeyboards");
+
+    post.title = "键".repeat(2000);
+    let caption = dailyPicCaption(tplt, &post, bot_config::TextFormat::Html).unwrap();
+    assert!(caption.encode_utf16().count() <= bot_config::TG_CAPTION_LIMIT);
+    assert!(caption.starts_with("<b>键"));
+    assert!(caption.ends_with("…</b> by someone (42) in r/MechanicalKeyboards"));
+
+    // Fits in the limit of characters, but not of UTF-16 code units.
+    post.title = "\u{1f3b9}".repeat(600);
+    let caption = dailyPicCaption(tplt, &post, bot_config::TextFormat::Html).unwrap();
+    assert!(caption.encode_utf16().count() <= bot_config::TG_CAPTION_LIMIT);
+    assert!(caption.ends_with("…</b> by someone (42) in r/MechanicalKeyboards"));
+
+    let tplt = "x".repeat(bot_config::TG_CAPTION_LIMIT + 1) + "${title}";
+    assert!(dailyPicCaption(&tplt, &post, bot_config::TextFormat::Html).is_err());
+}
+
+async fn trySendFirstPhotoFromPosts<'a>(
+    api: &bot::Api, config: &bot_config::ConfigParams, chat_id: i64,
+    candidates: &[Candidate<'a>]) -> Result<(Message, &'a MediaPost), Error>
+{
+    for candidate in candidates
     {
-        if posts[i].time_create > yesterday
+        let best_post = candidate.post;
+        info!("Best post today is {}, with {:?}.", best_post.id,
+              &best_post.media);
+
+        let caption_tplt = captionTemplateOf(config, chat_id, candidate.caption)?;
+        let format = config.general.text_format;
+        let caption = dailyPicCaption(caption_tplt, best_post, format)?;
+        let token = &config.general.token;
+        let sent = match &best_post.media
         {
-            posts.truncate(i + 1);
-            break;
+            reddit::PostMedia::Image(uri) =>
+                telegram::sendPhoto(api, uri, &caption, format, chat_id).await,
+            reddit::PostMedia::Gallery(uris) =>
+                telegram::sendMediaGroup(token, uris, &caption, format, chat_id)
+                .await.and_then(|msgs| msgs.into_iter().next().ok_or_else(
+                    || error!(RuntimeError, "Empty album sent"))),
+            reddit::PostMedia::Video(uri) =>
+                telegram::sendVideo(token, uri, &caption, format, chat_id).await,
+            reddit::PostMedia::Gif(uri) =>
+                telegram::sendAnimation(token, uri, &caption, format, chat_id)
+                .await,
+            reddit::PostMedia::Link(_) | reddit::PostMedia::Text =>
+                Err(error!(RuntimeError, "Post has no media")),
+        };
+        if let Ok(msg) = sent
+        {
+            return Ok((msg, best_post));
+        }
+        else
+        {
+            log_error!("Failed to send post {} as the best daily post.",
+                       best_post.id);
         }
     }
+    Err(error!(RuntimeError, "Failed to send best post"))
+}
 
-    debug!("Logging out of Reddit...");
-    if let Err(e) = redditor.logout().await
+/// Has the post, or the post it is a copy of, been sent to the chat?
+fn isPosted(chat_id: i64, post: &MediaPost) -> Result<bool, Error>
+{
+    let mut ids = vec![post.id.as_str()];
+    if let Some(origin) = &post.origin_id
     {
-        log_error!("{}", e);
+        ids.push(origin);
     }
-    Ok(posts)
+    chat_db::isPostSent(chat_id, &ids, &post.canonicalMediaUrl())
 }
 
-pub async fn sendBestRedditToday(api: &bot::Api, config: &bot_config::ConfigParams)
-                                 -> Result<Message, Error>
+/// Check the post against the filter. Return the reason if the post
+/// is rejected.
+fn checkPostFilter(filter: &bot_config::RedditFilter, post: &MediaPost)
+                   -> Option<String>
 {
-    if config.general.group_id.is_none()
+    if post.over_18 && !filter.allow_nsfw
     {
-        return Err(error!(RuntimeError, "No group ID specified"));
+        return Some("NSFW".to_owned());
     }
-
-    let chat_id = config.general.group_id.unwrap();
-    let posts = getRedditPostsToday(config).await?;
-    let mut best_posts: Vec<&reddit::Post> = posts.iter().filter(|p| {
-        if !p.isLink()
+    if post.spoiler && !filter.allow_spoiler
+    {
+        return Some("spoiler".to_owned());
+    }
+    let flair = post.flair.as_deref().unwrap_or("");
+    if !filter.include_flairs.is_empty() &&
+        !filter.include_flairs.iter().any(|f| f.eq_ignore_ascii_case(flair))
+    {
+        return Some(format!("flair '{}' not included", flair));
+    }
+    if filter.exclude_flairs.iter().any(|f| f.eq_ignore_ascii_case(flair))
+    {
+        return Some(format!("flair '{}' excluded", flair));
+    }
+    if let Some(min) = filter.min_score
+    {
+        if post.score < min
+        {
+            return Some(format!("score {} < {}", post.score, min));
+        }
+    }
+    if let Some(min) = filter.min_upvote_ratio
+    {
+        if post.upvote_ratio < min
         {
-            return false;
+            return Some(format!("upvote ratio {} < {}", post.upvote_ratio, min));
         }
+    }
+    if filter.blocked_authors.iter().any(
+        |a| a.eq_ignore_ascii_case(&post.author) ||
+            post.author_fullname.as_deref().map_or(false, |f| a.eq_ignore_ascii_case(f)))
+    {
+        return Some(format!("author {} blocked", post.author));
+    }
+    None
+}
 
-        let link = p.link.to_lowercase();
-        link.ends_with(".jpg") || link.ends_with(".jpeg") ||
-            link.ends_with(".png")
-    }).collect();
-    best_posts.sort_by_key(|p| p.score);
-    best_posts.reverse();
+#[test]
+fn testCheckPostFilter()
+{
+    let mut post = MediaPost::from(reddit::Post::new());
+    post.score = 100;
+    post.flair = Some("Photos".to_owned());
+    let mut filter = bot_config::RedditFilter::default();
+    filter.include_flairs = vec!["photos".to_owned(), "Build".to_owned()];
+    filter.min_score = Some(50);
+    assert_eq!(checkPostFilter(&filter, &post), None);
 
-    let msg = trySendFirstPhotoFromPosts(
-        api, chat_id, &best_posts, &config.reddit.daily_pic_caption).await?;
+    post.over_18 = true;
+    assert_eq!(checkPostFilter(&filter, &post), Some("NSFW".to_owned()));
+    post.over_18 = false;
+    post.flair = None;
+    assert!(checkPostFilter(&filter, &post).is_some());
+    post.flair = Some("Build".to_owned());
+    post.score = 10;
+    assert!(checkPostFilter(&filter, &post).is_some());
+    post.score = 100;
 
-    let mut info = RuntimeInfo::load()?;
-    info.last_msg_id = Some(i64::from(msg.id));
-    info.wa_count = 0;
-    info.save()?;
-    Ok(msg)
+    post.author = "new_name".to_owned();
+    post.author_fullname = Some("t2_abc".to_owned());
+    filter.blocked_authors = vec!["T2_ABC".to_owned()];
+    assert!(checkPostFilter(&filter, &post).is_some());
+    filter.blocked_authors = vec!["old_name".to_owned()];
+    assert_eq!(checkPostFilter(&filter, &post), None);
 }
 
-async fn onMessage(api: &bot::Api, config: &bot_config::ConfigParams, msg: Message)
-                   -> Result<(), Error>
+/// Can the post be sent as the daily picture?
+fn isMediaPost(p: &MediaPost) -> bool
 {
-    match msg.kind
+    match p.media
     {
-        // MessageKind::Text { ref data, .. } =>
-        MessageKind::Text {..} =>
-        {
-            if let Some(reply_to_box) = &msg.reply_to_message
-            {
-                onTextReply(api, config, &msg, reply_to_box.as_ref()).await?;
-            }
-        },
-        _ => ()
+        reddit::PostMedia::Link(_) | reddit::PostMedia::Text => false,
+        _ => true,
     }
-    Ok(())
 }
 
-async fn onChannelPost(api: &bot::Api, config: &bot_config::ConfigParams,
-                       post: bot::types::ChannelPost) -> Result<(), Error>
+pub fn redditEndpoints(config: &bot_config::ConfigParamsReddit) -> reddit::Endpoints
 {
-    match post.kind
-    {
-        MessageKind::NewChatMembers{ref data} =>
-        {
-            onNewChatMembers(api, &config, data, &post.chat).await?;
-        },
-        _ => ()
+    reddit::Endpoints {
+        auth_base: config.auth_url.clone(),
+        api_base: config.api_url.clone(),
     }
-    Ok(())
 }
 
-pub async fn startBot(config: &bot_config::ConfigParams)
+/// Rank the posts from all the sources, best first. Posts from
+/// Reddit that do not pass the filter are dropped.
+fn rankCandidates<'a, 'b>(
+    config: &bot_config::ConfigParamsReddit,
+    posts_by_source: &'a [(&'a DailySource<'b>, Vec<MediaPost>)])
+    -> Vec<Candidate<'a>>
 {
-    let api = bot::Api::new(&config.general.token);
-    let mut stream = api.stream();
-    info!("Entering update loop...");
-    while let Some(update) = stream.next().await
+    let mut candidates: Vec<Candidate> = Vec::new();
+    for (source, posts) in posts_by_source
     {
-        let update = match update
+        for post in posts.iter().filter(|p| isMediaPost(p))
         {
-            Err(e) => {log_error!("{}", e); continue;},
-            Ok(u) => u,
+            if source.is_reddit
+            {
+                if let Some(reason) = checkPostFilter(&config.filter, post)
+                {
+                    info!("Rejected {}: {}.", post.id, reason);
+                    continue;
+                }
+            }
+            candidates.push(Candidate {
+                post: post,
+                weight: source.weight,
+                caption: source.caption,
+                is_reddit: source.is_reddit,
+            });
+        }
+    }
+    let now = Utc::now();
+    candidates.sort_by(
+        |a, b| b.rankScore(config, &now)
+            .partial_cmp(&a.rankScore(config, &now))
+            .unwrap_or(std::cmp::Ordering::Equal));
+    candidates
+}
+
+#[test]
+fn testDailyPicFromMockReddit()
+{
+    let server = crate::reddit_mock::MockReddit::start();
+    let dir = tempfile::tempdir().unwrap();
+    let token_cache = dir.path().join("reddit-token.json");
+    let config: bot_config::ConfigParamsReddit = toml::from_str(
+        "client_id = \"\"\nclient_secret = \"\"\ndaily_pic_caption = \"\"")
+        .unwrap();
+
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let redditor = reddit::RedditQuerier::fromCacheOrUserlessAuthentication(
+            "id", "secret", token_cache.to_str().unwrap(), server.endpoints())
+            .await.unwrap();
+        let source = DailySource {
+            content: Box::new(content_source::RedditContent::new(
+                &redditor, &config.sources[0].subreddit,
+                reddit::TimeRange::day, DAILY_MAX_PAGES)),
+            weight: 1.0,
+            caption: None,
+            chat_id: 0,
+            is_reddit: true,
+        };
+        let posts = source.content.fetch(Utc::now() - chrono::Duration::days(1))
+            .await.unwrap();
+        assert_eq!(posts.len(), 2);
+        let posts_by_source = [(&source, posts)];
+        let candidates = rankCandidates(&config, &posts_by_source);
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].post.id, "t3_abc001");
+    });
+    assert!(token_cache.exists());
+}
+
+/// Remember the daily picture sent in message `pic.msg_id`, so that
+/// the wa-s to it are counted.
+fn recordDailyPic(pic: chat_db::DailyPicEntry) -> Result<(), Error>
+{
+    let chat_id = pic.chat_id;
+    let msg_id = pic.msg_id;
+    chat_db::addDailyPic(pic)?;
+
+    let mut info = RuntimeInfo::load()?;
+    if let Some(last) = info.last_daily_pics.get(&chat_id)
+    {
+        info!("Last daily pic ({}) in chat {} got {} was.", last.msg_id, chat_id,
+              last.wa_count);
+    }
+    info.last_daily_pics.insert(chat_id, LastDailyPic {
+        msg_id: msg_id,
+        wa_count: 0,
+    });
+    info.save()
+}
+
+/// The caption template of the daily picture, `caption` if the source
+/// has one.
+fn captionTemplateOf<'a>(config: &'a bot_config::ConfigParams, chat_id: i64,
+                         caption: Option<&'a str>) -> Result<&'a str, Error>
+{
+    match caption
+    {
+        Some(caption) => Ok(caption),
+        None => i18n::lookup(config, "daily_pic_caption", None, Some(chat_id)),
+    }
+}
+
+/// Text of an option in the poll of the daily picture. Telegram
+/// allows at most 100 characters.
+fn voteOptionText(index: usize, title: &str) -> String
+{
+    let text = format!("{}. {}", index + 1, title);
+    if text.chars().count() <= 100
+    {
+        text
+    }
+    else
+    {
+        text.chars().take(99).collect::<String>() + "…"
+    }
+}
+
+/// Send the poll of the daily vote in reply to the album, and remember
+/// it. If the poll is sent but cannot be remembered, it is deleted.
+async fn sendDailyPoll(api: &bot::Api, config: &bot_config::ConfigParams,
+                       vote: &bot_config::ConfigParamsVote, chat_id: i64,
+                       chosen: &[&Candidate<'_>], captions: Vec<String>,
+                       album: &[Message]) -> Result<(), Error>
+{
+    // Images that fail to send are left out of the album, and then
+    // the options cannot be matched with the album.
+    if album.len() != chosen.len()
+    {
+        return Err(error!(RuntimeError, format!(
+            "Only {} of {} pictures sent for the vote", album.len(), chosen.len())));
+    }
+
+    let token = &config.general.token;
+    let option_texts: Vec<String> = chosen.iter().enumerate()
+        .map(|(i, c)| voteOptionText(i, &c.post.title)).collect();
+    let question = i18n::lookup(config, "vote_question", None, Some(chat_id))?;
+    let (poll_msg_id, poll_id) = telegram::sendPoll(
+        token, chat_id, question, &option_texts,
+        Some(i64::from(album[0].id))).await?;
+
+    let now = Utc::now();
+    let options: Vec<chat_db::PollOptionEntry> =
+        chosen.iter().zip(album.iter()).zip(captions).enumerate()
+        .map(|(i, ((candidate, msg), caption))| chat_db::PollOptionEntry {
+            index: i as i64,
+            album_msg_id: i64::from(msg.id),
+            post_id: candidate.post.id.clone(),
+            title: candidate.post.title.clone(),
+            author: candidate.post.author.clone(),
+            url: candidate.post.url.clone(),
+            caption: caption,
+            score: i64::from(candidate.post.score),
+            votes: None,
+            media_url: candidate.post.canonicalMediaUrl(),
+        }).collect();
+    if let Err(e) = chat_db::addPoll(chat_db::PollEntry {
+        chat_id: chat_id,
+        msg_id: poll_msg_id,
+        poll_id: poll_id,
+        close_time: now + chrono::Duration::seconds(vote.duration_sec as i64),
+    }, &options)
+    {
+        // The poll would never be closed.
+        if let Err(e) = telegram::deleteMessage(
+            api, bot::types::ChatId::new(chat_id),
+            bot::types::MessageId::new(poll_msg_id)).await
+        {
+            log_error!("Failed to delete poll {}: {}", poll_id, e);
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Send the first images of the candidates as an album, and a poll to
+/// choose the daily picture from them. The poll is closed by
+/// `closeDueVotes()`. Return false if nothing is sent, e.g. when there
+/// are not enough pictures, so that the best post can be sent
+/// instead. Fail if the album is sent but the poll is not, after
+/// deleting the album.
+async fn startDailyVote(api: &bot::Api, config: &bot_config::ConfigParams,
+                        vote: &bot_config::ConfigParamsVote, chat_id: i64,
+                        candidates: &[Candidate<'_>]) -> Result<bool, Error>
+{
+    let count = vote.candidates.max(2).min(10);
+    let mut chosen: Vec<&Candidate> = Vec::new();
+    let mut uris: Vec<String> = Vec::new();
+    for candidate in candidates
+    {
+        let uri = match &candidate.post.media
+        {
+            reddit::PostMedia::Image(uri) => uri,
+            reddit::PostMedia::Gallery(uris) => match uris.first()
+            {
+                Some(uri) => uri,
+                None => continue,
+            },
+            _ => continue,
+        };
+        chosen.push(candidate);
+        uris.push(uri.clone());
+        if chosen.len() >= count
+        {
+            break;
+        }
+    }
+    if chosen.len() < 2
+    {
+        info!("Not enough pictures for a vote in chat {}.", chat_id);
+        return Ok(false);
+    }
+
+    let token = &config.general.token;
+    let format = config.general.text_format;
+    // Make the captions before sending anything, so that a bad
+    // template does not leave an album without a poll.
+    let captions = chosen.iter().map(|c| {
+        let caption_tplt = captionTemplateOf(config, chat_id, c.caption)?;
+        dailyPicCaption(caption_tplt, c.post, format)
+    }).collect::<Result<Vec<String>, Error>>()?;
+    info!("Starting a vote of {} pictures in chat {}...", chosen.len(), chat_id);
+    let album = match telegram::sendMediaGroup(token, &uris, "", format, chat_id)
+        .await
+    {
+        Ok(album) => album,
+        Err(e) =>
+        {
+            log_error!("Failed to send the album for the vote: {}", e);
+            return Ok(false);
+        },
+    };
+
+    if let Err(e) = sendDailyPoll(api, config, vote, chat_id, &chosen, captions,
+                                  &album).await
+    {
+        // Do not leave an album without a poll.
+        for msg in &album
+        {
+            if let Err(e) = telegram::deleteMessage(api, msg.chat.id(), msg.id).await
+            {
+                log_error!("Failed to delete message {} of the album: {}",
+                           msg.id, e);
+            }
+        }
+        return Err(e);
+    }
+    Ok(true)
+}
+
+/// The option with the most votes. Ties go to the option ranked
+/// higher by the bot.
+fn voteWinner(options: &[chat_db::PollOptionEntry])
+              -> Option<&chat_db::PollOptionEntry>
+{
+    let mut winner: Option<&chat_db::PollOptionEntry> = None;
+    for option in options
+    {
+        match winner
+        {
+            Some(w) if w.votes.unwrap_or(0) >= option.votes.unwrap_or(0) => (),
+            _ => winner = Some(option),
+        }
+    }
+    winner
+}
+
+/// Pin the winner of the vote, and unpin the winner pinned last time.
+/// The pin state of `pinInPlaceOfLast()` is left alone, so other pins
+/// of the bot are not affected.
+async fn pinVoteWinner(config: &bot_config::ConfigParams, chat_id: i64,
+                       msg_id: i64) -> Result<(), Error>
+{
+    let token = &config.general.token;
+    if let Some(last) = chat_db::getVotePin(chat_id)?
+    {
+        if let Err(e) = telegram::unpinMessage(token, chat_id, last).await
+        {
+            log_error!("{}", e);
+        }
+    }
+    info!("Pinning the winner {} in chat {}...", msg_id, chat_id);
+    telegram::pinMessage(token, chat_id, msg_id).await?;
+    chat_db::setVotePin(chat_id, msg_id)
+}
+
+/// Stop the poll, and pin or send again the winner as the daily
+/// picture.
+async fn closeVote(config: &bot_config::ConfigParams, poll: &chat_db::PollEntry)
+                   -> Result<(), Error>
+{
+    let vote = config.reddit.vote.as_ref().ok_or_else(
+        || error!(RuntimeError, "Vote is not configured"))?;
+    let token = &config.general.token;
+    // The poll may be stopped by an admin, or its message deleted.
+    // Still close it and take the first candidate as the winner.
+    let votes = match telegram::stopPoll(token, poll.chat_id, poll.msg_id).await
+    {
+        Ok(votes) => votes,
+        Err(e) =>
+        {
+            log_error!("Failed to stop poll {}: {}", poll.poll_id, e);
+            Vec::new()
+        },
+    };
+    if !chat_db::closePoll(poll.chat_id, poll.msg_id, &votes)?
+    {
+        return Ok(());
+    }
+
+    let options = chat_db::pollOptions(poll.chat_id, poll.msg_id)?;
+    let winner = voteWinner(&options).ok_or_else(
+        || error!(RuntimeError, format!("Poll {} has no option", poll.poll_id)))?;
+    info!("Post {} won the vote in chat {}.", winner.post_id, poll.chat_id);
+    let msg_id = if vote.pin_winner
+    {
+        pinVoteWinner(config, poll.chat_id, winner.album_msg_id).await?;
+        winner.album_msg_id
+    }
+    else
+    {
+        telegram::copyMessage(token, poll.chat_id, poll.chat_id,
+                              winner.album_msg_id, &winner.caption,
+                              config.general.text_format).await?
+    };
+    // Only the winner is sent as the daily picture. The others may
+    // still be candidates later.
+    chat_db::addSentPost(poll.chat_id, &winner.post_id, &winner.media_url,
+                         Utc::now())?;
+    recordDailyPic(chat_db::DailyPicEntry {
+        chat_id: poll.chat_id,
+        msg_id: msg_id,
+        post_id: winner.post_id.clone(),
+        title: winner.title.clone(),
+        author: winner.author.clone(),
+        url: winner.url.clone(),
+        time: Utc::now(),
+    })
+}
+
+/// Close the votes that are due.
+async fn closeDueVotes(config: &bot_config::ConfigParams) -> Result<(), Error>
+{
+    for poll in chat_db::duePolls(Utc::now())?
+    {
+        if let Err(e) = closeVote(config, &poll).await
+        {
+            log_error!("Failed to close poll {}: {}", poll.poll_id, e);
+        }
+    }
+    Ok(())
+}
+
+/// How the votes of the group compare with the scores on Reddit.
+#[derive(Debug, PartialEq)]
+struct VoteStats
+{
+    polls: usize,
+    /// Number of polls won by the option with the highest score.
+    agreed: usize,
+    /// Average rank of the winners by score among the options.
+    average_rank: f64,
+}
+
+fn voteStats(polls: &[Vec<chat_db::PollOptionEntry>]) -> VoteStats
+{
+    let mut stats = VoteStats { polls: 0, agreed: 0, average_rank: 0.0 };
+    let mut rank_sum = 0;
+    for options in polls
+    {
+        // The votes are unknown if the poll failed to stop.
+        if options.iter().any(|o| o.votes.is_none())
+        {
+            continue;
+        }
+        let winner = match voteWinner(options)
+        {
+            Some(w) => w,
+            None => continue,
+        };
+        let rank = 1 + options.iter().filter(|o| o.score > winner.score).count();
+        stats.polls += 1;
+        if rank == 1
+        {
+            stats.agreed += 1;
+        }
+        rank_sum += rank;
+    }
+    if stats.polls > 0
+    {
+        stats.average_rank = rank_sum as f64 / stats.polls as f64;
+    }
+    stats
+}
+
+#[test]
+fn testVoteStats()
+{
+    let option = |index: i64, score: i64, votes: i64| chat_db::PollOptionEntry {
+        index: index,
+        album_msg_id: index,
+        post_id: format!("t3_{}", index),
+        title: String::new(),
+        author: String::new(),
+        url: String::new(),
+        caption: String::new(),
+        score: score,
+        votes: Some(votes),
+        media_url: String::new(),
+    };
+    let mut unknown = vec![option(0, 10, 0), option(1, 100, 0)];
+    for o in &mut unknown
+    {
+        o.votes = None;
+    }
+    let polls = vec![
+        // Agrees with Reddit.
+        vec![option(0, 100, 3), option(1, 50, 1), option(2, 10, 0)],
+        // The lowest score wins.
+        vec![option(0, 100, 1), option(1, 50, 1), option(2, 10, 5)],
+        // A tie goes to the first option.
+        vec![option(0, 20, 2), option(1, 80, 2)],
+        // Failed to stop, so it is left out.
+        unknown,
+    ];
+    assert_eq!(voteWinner(&polls[2]).unwrap().index, 0);
+    assert_eq!(voteStats(&polls), VoteStats {
+        polls: 3,
+        agreed: 1,
+        average_rank: 2.0,
+    });
+    assert_eq!(voteStats(&[]).polls, 0);
+}
+
+/// Send the best picture today from `sources` to the chat. Posts from
+/// all the sources are ranked together. If voting is configured, send
+/// the best ones for the group to vote instead, unless the vote cannot
+/// be started without sending anything.
+async fn sendDailyPicToChat(
+    api: &bot::Api, config: &bot_config::ConfigParams, chat_id: i64,
+    sources: &[&DailySource<'_>]) -> Result<(), Error>
+{
+    let yesterday = Utc::now() - chrono::Duration::days(1);
+    let mut posts_by_source = Vec::new();
+    for source in sources
+    {
+        match source.content.fetch(yesterday).await
+        {
+            Ok(posts) => posts_by_source.push((*source, posts)),
+            Err(e) => log_error!("Failed to get posts from {}: {}",
+                                 source.content.name(), e),
+        }
+    }
+
+    let mut candidates: Vec<Candidate> = Vec::new();
+    for candidate in rankCandidates(&config.reddit, &posts_by_source)
+    {
+        if isPosted(chat_id, candidate.post)?
+        {
+            debug!("Skipping {}, which is already sent.", candidate.post.id);
+            continue;
+        }
+        candidates.push(candidate);
+    }
+
+    if let Some(vote) = &config.reddit.vote
+    {
+        if startDailyVote(api, config, vote, chat_id, &candidates).await?
+        {
+            return Ok(());
+        }
+        info!("No vote started, sending the best post instead.");
+    }
+
+    let (msg, post) = trySendFirstPhotoFromPosts(
+        api, config, chat_id, &candidates).await?;
+    chat_db::addSentPost(chat_id, &post.id, &post.canonicalMediaUrl(),
+                         chrono::Utc::now())?;
+
+    recordDailyPic(chat_db::DailyPicEntry {
+        chat_id: chat_id,
+        msg_id: i64::from(msg.id),
+        post_id: post.id.clone(),
+        title: post.title.clone(),
+        author: post.author.clone(),
+        url: post.url.clone(),
+        time: chrono::Utc.timestamp(msg.date, 0),
+    })
+}
+
+/// Send the best picture today to each chat. If `source_name` is
+/// given, only use the source with that subreddit or name, otherwise
+/// the sources of a chat are ranked together.
+pub async fn sendBestRedditToday(api: &bot::Api, config: &bot_config::ConfigParams,
+                                 source_name: Option<&str>) -> Result<(), Error>
+{
+    let selected = |name: &str| match source_name
+    {
+        Some(selected_name) => name.eq_ignore_ascii_case(selected_name),
+        None => true,
+    };
+    let reddit_sources: Vec<&bot_config::RedditSource> = config.reddit.sources
+        .iter().filter(|s| selected(&s.subreddit)).collect();
+    let other_sources: Vec<&bot_config::ContentSourceConfig> =
+        config.content_sources.iter().filter(|s| selected(&s.name)).collect();
+    if reddit_sources.is_empty() && other_sources.is_empty()
+    {
+        return Err(error!(RuntimeError, "No source to use"));
+    }
+
+    let redditor = if reddit_sources.is_empty()
+    {
+        None
+    }
+    else
+    {
+        // API rule says must authenticate.
+        // https://github.com/reddit-archive/reddit/wiki/API
+        debug!("Authenticating on Reddit...");
+        Some(reddit::RedditQuerier::fromCacheOrUserlessAuthentication(
+            &config.reddit.client_id, &config.reddit.client_secret,
+            &config.reddit.token_cache, redditEndpoints(&config.reddit)).await?)
+    };
+
+    let chatOf = |chat_id: Option<i64>| chat_id.or(config.general.group_id)
+        .ok_or_else(|| error!(RuntimeError, "No group ID specified"));
+    let mut sources: Vec<DailySource> = Vec::new();
+    if let Some(redditor) = &redditor
+    {
+        for source in reddit_sources
+        {
+            sources.push(DailySource {
+                content: Box::new(content_source::RedditContent::new(
+                    redditor, &source.subreddit, reddit::TimeRange::day,
+                    DAILY_MAX_PAGES)),
+                weight: source.weight,
+                caption: source.caption.as_deref(),
+                chat_id: chatOf(source.chat_id)?,
+                is_reddit: true,
+            });
+        }
+    }
+    for source in other_sources
+    {
+        sources.push(DailySource {
+            content: content_source::fromConfig(source)?,
+            weight: source.weight,
+            caption: source.caption.as_deref(),
+            chat_id: chatOf(source.chat_id)?,
+            is_reddit: false,
+        });
+    }
+
+    let mut sources_by_chat: BTreeMap<i64, Vec<&DailySource>> = BTreeMap::new();
+    for source in &sources
+    {
+        sources_by_chat.entry(source.chat_id).or_insert_with(Vec::new)
+            .push(source);
+    }
+
+    let mut result = Ok(());
+    for (chat_id, chat_sources) in &sources_by_chat
+    {
+        if let Err(e) = sendDailyPicToChat(api, config, *chat_id, chat_sources)
+            .await
+        {
+            log_error!("Failed to send best post to {}: {}", chat_id, e);
+            result = Err(e);
+        }
+    }
+    result
+}
+
+/// Make the caption of the weekly album. Leave out the posts that do
+/// not fit in a caption.
+fn redditWeeklyCaption(config: &bot_config::ConfigParams, chat_id: i64,
+                       posts: &[&MediaPost]) -> Result<String, Error>
+{
+    let format = config.general.text_format;
+    let esc = |text: &str| telegram::escape(text, format);
+    let item_tplt = i18n::lookup(config, "reddit_weekly_item_template", None,
+                                 Some(chat_id))?;
+    let mut caption = String::new();
+    let mut lines: Vec<String> = Vec::new();
+    for (i, post) in posts.iter().enumerate()
+    {
+        lines.push(utils::SimpleTemplate::new(item_tplt)
+                   .apply("rank", i + 1).apply("title", esc(&post.title))
+                   .apply("author", esc(&post.author)).apply("score", post.score)
+                   .apply("subreddit", esc(&post.community))
+                   .apply("url", esc(&post.url)).result());
+        let text = i18n::template(config, "reddit_weekly_template", None,
+                                  Some(chat_id))?
+            .apply("list", lines.join("\n")).result();
+        if text.encode_utf16().count() > bot_config::TG_CAPTION_LIMIT
+        {
+            break;
+        }
+        caption = text;
+    }
+    Ok(caption)
+}
+
+/// Send the best pictures this week from the subreddits to the chat,
+/// as an album.
+pub async fn sendRedditWeekly(config: &bot_config::ConfigParams, chat_id: i64)
+                              -> Result<(), Error>
+{
+    debug!("Authenticating on Reddit...");
+    let redditor = reddit::RedditQuerier::fromCacheOrUserlessAuthentication(
+        &config.reddit.client_id, &config.reddit.client_secret,
+        &config.reddit.token_cache, redditEndpoints(&config.reddit)).await?;
+    let sources: Vec<DailySource> = config.reddit.sources.iter().map(
+        |source| DailySource {
+            content: Box::new(content_source::RedditContent::new(
+                &redditor, &source.subreddit, reddit::TimeRange::week,
+                WEEKLY_MAX_PAGES)),
+            weight: source.weight,
+            caption: None,
+            chat_id: chat_id,
+            is_reddit: true,
+        }).collect();
+
+    let last_week = Utc::now() - chrono::Duration::weeks(1);
+    let mut posts_by_source = Vec::new();
+    for source in &sources
+    {
+        match source.content.fetch(last_week).await
+        {
+            Ok(posts) => posts_by_source.push((source, posts)),
+            Err(e) => log_error!("Failed to get posts from {}: {}",
+                                 source.content.name(), e),
+        }
+    }
+
+    let count = config.reddit.weekly_count.max(WEEKLY_MIN_COUNT).min(10);
+    let mut posts: Vec<&MediaPost> = Vec::new();
+    let mut uris: Vec<String> = Vec::new();
+    for candidate in rankCandidates(&config.reddit, &posts_by_source)
+    {
+        // Only the first image of a gallery goes in the album.
+        let uri = match &candidate.post.media
+        {
+            reddit::PostMedia::Image(uri) => uri,
+            reddit::PostMedia::Gallery(uris) => match uris.first()
+            {
+                Some(uri) => uri,
+                None => continue,
+            },
+            _ => continue,
+        };
+        posts.push(candidate.post);
+        uris.push(uri.clone());
+        if posts.len() >= count
+        {
+            break;
+        }
+    }
+    if posts.len() < WEEKLY_MIN_COUNT
+    {
+        return Err(error!(RuntimeError, format!(
+            "Only {} pictures found for the weekly album", posts.len())));
+    }
+
+    // List only the posts whose images are actually sent.
+    let makeCaption = |indices: &[usize]| {
+        if indices.len() < WEEKLY_MIN_COUNT
+        {
+            return Err(error!(RuntimeError, format!(
+                "Only {} pictures left for the weekly album", indices.len())));
+        }
+        let sent: Vec<&MediaPost> = indices.iter().map(|i| posts[*i]).collect();
+        redditWeeklyCaption(config, chat_id, &sent)
+    };
+    telegram::sendMediaGroupWith(&config.general.token, &uris, makeCaption,
+                                 config.general.text_format, chat_id).await?;
+    Ok(())
+}
+
+/// Split a command message into the command name and its arguments.
+/// Return `None` if `text` is not a command, or if it is a command
+/// addressed to another bot.
+fn parseCommand<'a>(text: &'a str, bot_username: &str)
+                    -> Option<(&'a str, Vec<&'a str>)>
+{
+    if !text.starts_with('/')
+    {
+        return None;
+    }
+    let mut parts = text.split_whitespace();
+    let mut cmd_parts = parts.next()?[1..].splitn(2, '@');
+    let cmd = cmd_parts.next()?;
+    if let Some(target) = cmd_parts.next()
+    {
+        if target != bot_username
+        {
+            return None;
+        }
+    }
+    Some((cmd, parts.collect()))
+}
+
+#[test]
+fn testParseCommand()
+{
+    assert_eq!(parseCommand("/bestpics", "keybot"), Some(("bestpics", vec![])));
+    assert_eq!(parseCommand("/bestpics@keybot 7", "keybot"),
+               Some(("bestpics", vec!["7"])));
+    assert_eq!(parseCommand("/bestpics@otherbot", "keybot"), None);
+    assert_eq!(parseCommand("哇", "keybot"), None);
+}
+
+/// Build the list of the most wa-ed daily pictures during the last
+/// `time_period`, formatted with the template `tplt_key` in the
+/// language `lang`.
+fn bestPicsText(config: &bot_config::ConfigParams, time_period: chrono::Duration,
+                tplt_key: &str, lang: Option<&str>, chat_id: i64)
+                -> Result<String, Error>
+{
+    let pics = chat_db::bestDailyPics(chat_id, time_period, BEST_PICS_COUNT)?;
+    let item_tplt = i18n::lookup(config, "bestpics_item_template", lang,
+                                 Some(chat_id))?;
+    let list: Vec<String> = pics.iter().enumerate().map(|(i, (pic, count))| {
+        utils::SimpleTemplate::new(item_tplt)
+            .apply("rank", i + 1).apply("title", &pic.title)
+            .apply("author", &pic.author).apply("count", count)
+            .apply("url", &pic.url).result()
+    }).collect();
+    Ok(i18n::template(config, tplt_key, lang, Some(chat_id))?
+       .apply("days", time_period.num_days()).apply("list", list.join("\n"))
+       .result())
+}
+
+/// Parse the number of days in the first argument of a command, or
+/// use `default` if there is no argument. Return `None` if it is not
+/// a number from 1 to `MAX_COMMAND_DAYS`.
+fn parseDays(args: &[&str], default: i64) -> Option<i64>
+{
+    let days = match args.first()
+    {
+        Some(arg) => arg.parse().ok()?,
+        None => default,
+    };
+    if days >= 1 && days <= MAX_COMMAND_DAYS
+    {
+        Some(days)
+    }
+    else
+    {
+        None
+    }
+}
+
+#[test]
+fn testParseDays()
+{
+    assert_eq!(parseDays(&[], 30), Some(30));
+    assert_eq!(parseDays(&["7"], 30), Some(7));
+    assert_eq!(parseDays(&["0"], 30), None);
+    assert_eq!(parseDays(&["-3"], 30), None);
+    assert_eq!(parseDays(&["999999999999999"], 30), None);
+    assert_eq!(parseDays(&["week"], 30), None);
+}
+
+/// Tell the user that the number of days in the command is invalid.
+async fn replyInvalidDays(api: &bot::Api, config: &bot_config::ConfigParams,
+                          msg: &Message) -> Result<(), Error>
+{
+    let text = i18n::template(config, "invalid_days_template",
+                              msg.from.language_code.as_deref(),
+                              Some(i64::from(msg.chat.id())))?
+        .apply("max", MAX_COMMAND_DAYS).result();
+    telegram::sendFormatted(api, msg.chat.id(), &text, config.general.text_format,
+                            Some(msg.id)).await?;
+    Ok(())
+}
+
+async fn onBestPicsCommand(api: &bot::Api, config: &bot_config::ConfigParams,
+                           msg: &Message, args: &[&str]) -> Result<(), Error>
+{
+    let days = match parseDays(args, 30)
+    {
+        Some(days) => days,
+        None => return replyInvalidDays(api, config, msg).await,
+    };
+    let text = bestPicsText(config, chrono::Duration::days(days),
+                            "bestpics_template",
+                            msg.from.language_code.as_deref(),
+                            i64::from(msg.chat.id()))?;
+    api.send(SendMessage::new(msg.chat.id(), text).reply_to(msg.id)
+             .disable_preview()).await
+        .map_err(|_| error!(RuntimeError, "Failed to send best pics"))?;
+    Ok(())
+}
+
+/// Tell how the votes of the group on the daily picture compare with
+/// the scores on Reddit.
+async fn onVoteStatsCommand(api: &bot::Api, config: &bot_config::ConfigParams,
+                            msg: &Message) -> Result<(), Error>
+{
+    let chat_id = i64::from(msg.chat.id());
+    let stats = voteStats(&chat_db::closedPollOptions(chat_id)?);
+    let text = i18n::template(config, "vote_stats_template",
+                              msg.from.language_code.as_deref(), Some(chat_id))?
+        .apply("polls", stats.polls).apply("agreed", stats.agreed)
+        .apply("rank", format!("{:.1}", stats.average_rank)).result();
+    telegram::sendFormatted(api, msg.chat.id(), &text, config.general.text_format,
+                            Some(msg.id)).await?;
+    Ok(())
+}
+
+/// Let an admin review the flagged wa-s.
+async fn onFlaggedCommand(api: &bot::Api, config: &bot_config::ConfigParams,
+                          msg: &Message, args: &[&str]) -> Result<(), Error>
+{
+    if !telegram::isChatAdmin(api, msg.chat.id(), msg.from.id).await?
+    {
+        debug!("{} is not an admin.", telegram::getUsername(&msg.from));
+        return Ok(());
+    }
+    let days = match parseDays(args, 7)
+    {
+        Some(days) => days,
+        None => return replyInvalidDays(api, config, msg).await,
+    };
+
+    let mut lines: Vec<String> = Vec::new();
+    for (waer, count, reason) in chat_db::flaggedWaers(
+        i64::from(msg.chat.id()), chrono::Duration::days(days))?
+    {
+        let name = match telegram::getChatMember(
+            api, i64::from(msg.chat.id()), waer).await
+        {
+            Ok(user) => telegram::getUsername(&user),
+            Err(_) => waer.to_string(),
+        };
+        lines.push(format!("{}: {} ({})", name, count, reason));
+    }
+    let text = i18n::template(config, "flagged_template",
+                              msg.from.language_code.as_deref(),
+                              Some(i64::from(msg.chat.id())))?
+        .apply("days", days).apply("list", lines.join("\n")).result();
+    api.send(SendMessage::new(msg.chat.id(), text).reply_to(msg.id)).await
+        .map_err(|_| error!(RuntimeError, "Failed to send flagged was"))?;
+    Ok(())
+}
+
+async fn onCommand(api: &bot::Api, config: &bot_config::ConfigParams,
+                   msg: &Message, cmd: &str, args: &[&str]) -> Result<(), Error>
+{
+    debug!("Command {} received.", cmd);
+    match cmd
+    {
+        "bestpics" => onBestPicsCommand(api, config, msg, args).await,
+        "flagged" => onFlaggedCommand(api, config, msg, args).await,
+        "votestats" => onVoteStatsCommand(api, config, msg).await,
+        "subscribe" =>
+            reddit_watch::onSubscribeCommand(api, config, msg, args, true).await,
+        "unsubscribe" =>
+            reddit_watch::onSubscribeCommand(api, config, msg, args, false).await,
+        _ => Ok(()),
+    }
+}
+
+async fn onMessage(api: &bot::Api, config: &bot_config::ConfigParams, msg: Message)
+                   -> Result<(), Error>
+{
+    let chat = ChatInfo {
+        id: i64::from(msg.chat.id()),
+        title: telegram::getChatTitle(&msg.chat),
+    };
+    if onMemberEvent(api, config, &msg.kind, &chat, Some(&msg.from)).await?
+    {
+        return Ok(());
+    }
+
+    match msg.kind
+    {
+        MessageKind::Text { ref data, .. } =>
+        {
+            if let Some((cmd, args)) = parseCommand(data, &config.general.username)
+            {
+                onCommand(api, config, &msg, cmd, &args).await?;
+            }
+            else if let Some(reply_to_box) = &msg.reply_to_message
+            {
+                onTextReply(api, config, &msg, reply_to_box.as_ref()).await?;
+            }
+        },
+        _ => ()
+    }
+    Ok(())
+}
+
+async fn onChannelPost(api: &bot::Api, config: &bot_config::ConfigParams,
+                       post: bot::types::ChannelPost) -> Result<(), Error>
+{
+    let chat = ChatInfo {
+        id: i64::from(post.chat.id),
+        title: post.chat.title.clone(),
+    };
+    onMemberEvent(api, config, &post.kind, &chat, None).await?;
+    Ok(())
+}
+
+pub async fn startBot(config: &bot_config::ConfigParams)
+{
+    let api = bot::Api::new(&config.general.token);
+    if let Err(e) = captcha::resumePending(&api)
+    {
+        log_error!("{}", e);
+    }
+    if let Err(e) = resumeDeletions(&api)
+    {
+        log_error!("{}", e);
+    }
+    if let Err(e) = resumeWelcomes(&api, config)
+    {
+        log_error!("{}", e);
+    }
+    if config.reddit.vote.is_some()
+    {
+        let config = config.clone();
+        tokio::spawn(async move {
+            loop
+            {
+                if let Err(e) = closeDueVotes(&config).await
+                {
+                    log_error!("{}", e);
+                }
+                tokio::time::delay_for(VOTE_CHECK_INTERVAL).await;
+            }
+        });
+    }
+    if config.reddit.watch.is_some()
+    {
+        reddit_watch::start(&api, config);
+    }
+    let mut stream = api.stream();
+    info!("Entering update loop...");
+    while let Some(update) = stream.next().await
+    {
+        let update = match update
+        {
+            Err(e) => {log_error!("{}", e); continue;},
+            Ok(u) => u,
         };
 
         let api = api.clone();
@@ -375,6 +1886,14 @@ pub async fn startBot(config: &bot_config::ConfigParams)
                         log_error!("{}", e);
                     }
                 },
+                bot::types::UpdateKind::CallbackQuery(query) =>
+                {
+                    if let Err(e) = captcha::onCallbackQuery(&api, &config, &query)
+                        .await
+                    {
+                        log_error!("{}", e);
+                    }
+                },
                 _ => (),
             }
         });
@@ -382,34 +1901,141 @@ pub async fn startBot(config: &bot_config::ConfigParams)
 }
 
 pub async fn sendBestWaer(
-    api: &bot::Api, chat_id: i64, time_period: chrono::Duration, msg_tplt: &str)
-    -> Result<(), Error>
+    api: &bot::Api, config: &bot_config::ConfigParams, chat_id: i64,
+    time_period: chrono::Duration, msg_tplt: &str) -> Result<(), Error>
 {
-    let (waer, count) = chat_db::bestWaer(time_period)?;
+    let (waer, count) = chat_db::bestWaer(chat_id, time_period)?;
     info!("Best waer the last {}, with {} was.", time_period, count);
 
-    let username = telegram::getUserFullname(
-        &telegram::getChatMember(api, chat_id, waer).await?);
+    let name = match telegram::getChatMember(api, chat_id, waer).await
+    {
+        Ok(user) => telegram::mention(&user, config.general.mention_style,
+                                      config.general.text_format),
+        // The waer may have left the chat.
+        Err(e) => telegram::escape(
+            &chat_db::cachedMemberName(waer)?.ok_or(e)?,
+            config.general.text_format),
+    };
 
-    api.send(SendMessage::new(
-        bot::types::ChatId::new(chat_id),
+    telegram::sendFormatted(
+        api, bot::types::ChatId::new(chat_id),
         &utils::SimpleTemplate::new(msg_tplt)
-            .apply("name", username).apply("count", count).result())).await
+            .apply("name", name).apply("count", count).result(),
+        config.general.text_format, None).await
         .map_err(|_| error!(RuntimeError, "Failed to send best waer"))?;
     Ok(())
 }
 
 pub async fn sendBestWaable(
-    api: &bot::Api, chat_id: i64, time_period: chrono::Duration, msg_tplt: &str)
+    api: &bot::Api, config: &bot_config::ConfigParams, chat_id: i64,
+    time_period: chrono::Duration, msg_tplt: &str, pin: bot_config::PinTarget)
     -> Result<(), Error>
 {
-    let (waable, count) = chat_db::bestWaable(time_period)?;
+    let (waable, count) = chat_db::bestWaable(chat_id, time_period)?;
     info!("Best waable in the last {}, with {} was.", time_period, count);
 
+    let post = telegram::sendFormatted(
+        api, bot::types::ChatId::new(chat_id),
+        &utils::SimpleTemplate::new(msg_tplt).apply("count", count).result(),
+        config.general.text_format, Some(bot::types::MessageId::new(waable)))
+        .await.map_err(|_| error!(RuntimeError, "Failed to send best waable"))?;
+
+    let pin_id = match pin
+    {
+        bot_config::PinTarget::None => return Ok(()),
+        bot_config::PinTarget::Waable => waable,
+        bot_config::PinTarget::Announcement => i64::from(telegram::getPostId(&post)),
+    };
+    pinInPlaceOfLast(config, chat_id, pin_id).await
+}
+
+/// Pin the message with `msg_id`, and unpin the message pinned by the
+/// bot last time. Remember what was pinned before the bot pinned
+/// anything, so that it can be restored by `restorePin()`.
+async fn pinInPlaceOfLast(config: &bot_config::ConfigParams, chat_id: i64,
+                          msg_id: i64) -> Result<(), Error>
+{
+    let token = &config.general.token;
+    let current = telegram::getPinnedMsgId(token, chat_id).await?;
+    let original = match chat_db::getPinState(chat_id)?
+    {
+        Some(state) =>
+        {
+            if let Err(e) = telegram::unpinMessage(token, chat_id, state.bot_pin).await
+            {
+                log_error!("{}", e);
+            }
+            // If someone pinned something else after the bot, that
+            // is the one to restore.
+            if current == Some(state.bot_pin)
+            {
+                state.original_pin
+            }
+            else
+            {
+                current
+            }
+        },
+        None => current,
+    };
+
+    info!("Pinning message {} in chat {}...", msg_id, chat_id);
+    telegram::pinMessage(token, chat_id, msg_id).await?;
+    chat_db::setPinState(chat_db::PinState {
+        chat_id: chat_id,
+        bot_pin: msg_id,
+        original_pin: original,
+    })
+}
+
+/// Unpin the message pinned by the bot, and pin the message that was
+/// pinned before.
+pub async fn restorePin(config: &bot_config::ConfigParams, chat_id: i64)
+                        -> Result<(), Error>
+{
+    let token = &config.general.token;
+    let state = match chat_db::getPinState(chat_id)?
+    {
+        Some(state) => state,
+        None => return Ok(()),
+    };
+    if let Err(e) = telegram::unpinMessage(token, chat_id, state.bot_pin).await
+    {
+        log_error!("{}", e);
+    }
+    if let Some(original) = state.original_pin
+    {
+        info!("Restoring pinned message {} in chat {}...", original, chat_id);
+        telegram::pinMessage(token, chat_id, original).await?;
+    }
+    chat_db::clearPinState(chat_id)
+}
+
+pub async fn sendBestPics(
+    api: &bot::Api, config: &bot_config::ConfigParams, chat_id: i64,
+    time_period: chrono::Duration, tplt_key: &str) -> Result<(), Error>
+{
+    let text = bestPicsText(config, time_period, tplt_key, None, chat_id)?;
+    info!("Sending best daily pics in the last {}.", time_period);
+    api.send(SendMessage::new(bot::types::ChatId::new(chat_id), text)
+             .disable_preview()).await
+        .map_err(|_| error!(RuntimeError, "Failed to send best pics"))?;
+    Ok(())
+}
+
+/// Send the numbers of members who joined and left the chat during
+/// the last `time_period`.
+pub async fn sendMemberGrowth(
+    api: &bot::Api, chat_id: i64, time_period: chrono::Duration, msg_tplt: &str)
+    -> Result<(), Error>
+{
+    let (joined, left) = chat_db::memberGrowth(chat_id, time_period)?;
+    info!("{} joined and {} left in the last {}.", joined, left, time_period);
     api.send(SendMessage::new(
         bot::types::ChatId::new(chat_id),
-        &utils::SimpleTemplate::new(msg_tplt).apply("count", count).result())
-             .reply_to(bot::types::MessageId::new(waable))).await
-        .map_err(|_| error!(RuntimeError, "Failed to send best waable"))?;
+        &utils::SimpleTemplate::new(msg_tplt).apply("joined", joined)
+            .apply("left", left).apply("growth", joined as i64 - left as i64)
+            .result())).await
+        .map_err(|_| error!(RuntimeError, "Failed to send member growth"))?;
     Ok(())
 }
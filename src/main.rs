@@ -17,6 +17,12 @@ mod bot_config;
 mod telegram;
 mod keybot;
 mod chat_db;
+mod captcha;
+mod i18n;
+mod content_source;
+mod reddit_watch;
+#[cfg(test)]
+mod reddit_mock;
 
 use crate::error::Error;
 
@@ -27,6 +33,13 @@ fn readConfig() -> Result<bot_config::ConfigParams, error::Error>
     bot_config::ConfigParams::fromFile(conf_file)
 }
 
+/// Return the template `key` in the language of the group.
+fn groupTemplate<'a>(config: &'a bot_config::ConfigParams, key: &str)
+                     -> Result<&'a str, error::Error>
+{
+    i18n::lookup(config, key, None, config.general.group_id)
+}
+
 fn lastMonth(from_time: &chrono::DateTime<chrono::Utc>)
              -> Result<chrono::DateTime<chrono::Utc>, error::Error>
 {
@@ -52,7 +65,12 @@ async fn main() -> Result<(), error::Error>
         .author("@MetroWind")
         .about("A bot for a certain Telegram group")
         .subcommand(clap::App::new("send-reddit-best")
-                    .about("Send r/mk's best pic today."))
+                    .about("Send the best pic today.")
+                    .arg(clap::Arg::with_name("source")
+                         .long("source").takes_value(true)
+                         .help("Only use the source with this subreddit or name")))
+        .subcommand(clap::App::new("send-reddit-weekly")
+                    .about("Send the best Reddit pics this week as an album."))
         .subcommand(clap::App::new("send-weekly-waer")
                     .about("Send weekly waer."))
         .subcommand(clap::App::new("send-weekly-waable")
@@ -61,6 +79,12 @@ async fn main() -> Result<(), error::Error>
                     .about("Send monthly waer."))
         .subcommand(clap::App::new("send-monthly-waable")
                     .about("Send monthly waable."))
+        .subcommand(clap::App::new("send-monthly-members")
+                    .about("Send the numbers of members joined and left this month."))
+        .subcommand(clap::App::new("restore-pin")
+                    .about("Unpin the weekly waable and restore the previous pin."))
+        .subcommand(clap::App::new("send-monthly-bestpics")
+                    .about("Send the most wa-ed daily pics this month."))
         .get_matches();
 
     let config = readConfig()?;
@@ -68,51 +92,81 @@ async fn main() -> Result<(), error::Error>
     {
         keybot::RuntimeInfo::new().save()?;
     }
+    chat_db::initialize(config.general.group_id)?;
 
     match opts.subcommand_name()
     {
         Some("send-reddit-best") =>
         {
             let api = bot::Api::new(&config.general.token);
-            keybot::sendBestRedditToday(&api, &config).await?;
-            return Ok(());
+            let source = opts.subcommand_matches("send-reddit-best")
+                .and_then(|m| m.value_of("source"));
+            return keybot::sendBestRedditToday(&api, &config, source).await;
+        },
+        Some("send-reddit-weekly") =>
+        {
+            return keybot::sendRedditWeekly(
+                &config, config.general.group_id.unwrap()).await;
         },
         Some("send-weekly-waer") =>
         {
             let api = bot::Api::new(&config.general.token);
             return keybot::sendBestWaer(
-                &api, config.general.group_id.unwrap(), chrono::Duration::days(7),
-                &config.general.weekly_waer_template).await;
+                &api, &config, config.general.group_id.unwrap(),
+                chrono::Duration::days(7),
+                groupTemplate(&config, "weekly_waer_template")?).await;
         },
         Some("send-weekly-waable") =>
         {
             let api = bot::Api::new(&config.general.token);
             return keybot::sendBestWaable(
-                &api, config.general.group_id.unwrap(), chrono::Duration::days(7),
-                &config.general.weekly_waable_template).await;
+                &api, &config, config.general.group_id.unwrap(),
+                chrono::Duration::days(7),
+                groupTemplate(&config, "weekly_waable_template")?,
+                config.general.pin_weekly_waable).await;
         },
         Some("send-monthly-waer") =>
         {
             let api = bot::Api::new(&config.general.token);
             let now = chrono::Utc::now();
             return keybot::sendBestWaer(
-                &api, config.general.group_id.unwrap(), now - lastMonth(&now)?,
-                &config.general.monthly_waer_template).await;
+                &api, &config, config.general.group_id.unwrap(),
+                now - lastMonth(&now)?,
+                groupTemplate(&config, "monthly_waer_template")?).await;
         },
         Some("send-monthly-waable") =>
         {
             let api = bot::Api::new(&config.general.token);
             let now = chrono::Utc::now();
             return keybot::sendBestWaable(
+                &api, &config, config.general.group_id.unwrap(),
+                now - lastMonth(&now)?,
+                groupTemplate(&config, "monthly_waable_template")?,
+                bot_config::PinTarget::None).await;
+        },
+        Some("send-monthly-members") =>
+        {
+            let api = bot::Api::new(&config.general.token);
+            let now = chrono::Utc::now();
+            return keybot::sendMemberGrowth(
                 &api, config.general.group_id.unwrap(), now - lastMonth(&now)?,
-                &config.general.monthly_waable_template).await;
+                groupTemplate(&config, "monthly_members_template")?).await;
+        },
+        Some("restore-pin") =>
+        {
+            return keybot::restorePin(&config, config.general.group_id.unwrap())
+                .await;
+        },
+        Some("send-monthly-bestpics") =>
+        {
+            let api = bot::Api::new(&config.general.token);
+            let now = chrono::Utc::now();
+            return keybot::sendBestPics(
+                &api, &config, config.general.group_id.unwrap(),
+                now - lastMonth(&now)?, "monthly_bestpics_template").await;
         },
         None =>
         {
-            if !std::path::Path::new(chat_db::DB_FILENAME).exists()
-            {
-                chat_db::initialize()?;
-            }
             keybot::startBot(&config).await;
         },
         _ =>
@@ -1,11 +1,16 @@
 use std::vec::Vec;
-use std::collections::HashMap;
 use std::time;
 use std::fmt;
+use std::fs;
+use std::sync::Mutex;
 
-// use log::info;
+use log::{info,debug};
+use log::error as log_error;
+use tokio;
+use futures::stream::{self, Stream, TryStreamExt};
 use reqwest;
 use reqwest::blocking as requests;
+use serde::{Serialize, Deserialize};
 use serde_json;
 use uuid;
 use chrono::prelude::*;
@@ -13,65 +18,247 @@ use chrono::prelude::*;
 use crate::error::Error;
 use crate::simple_http_server;
 
+/// Response of an access token request.
+#[derive(Deserialize)]
+struct TokenResponse
+{
+    access_token: String,
+    refresh_token: Option<String>,
+    /// Number of seconds before the token expires.
+    expires_in: Option<i64>,
+}
+
+/// Reddit reports errors like this, sometimes with a good HTTP status.
+#[derive(Deserialize)]
+struct ErrorResponse
+{
+    error: serde_json::Value,
+    message: Option<String>,
+}
+
+/// Return an error if `body` is an error response from Reddit.
+/// `context` describes the request in the error message.
+fn checkErrorResponse(body: &str, context: &str) -> Result<(), Error>
+{
+    if let Ok(err) = serde_json::from_str::<ErrorResponse>(body)
+    {
+        return Err(error!(RedditError, format!(
+            "{} returned error {}: {}", context, err.error,
+            err.message.unwrap_or_default())));
+    }
+    Ok(())
+}
+
+fn parseTokenResponse(body: &str) -> Result<TokenResponse, Error>
+{
+    checkErrorResponse(body, "Token request")?;
+    serde_json::from_str(body).map_err(
+        |e| error!(RedditError, format!("Invalid token response: {}", e)))
+}
+
+#[test]
+fn testParseTokenResponse()
+{
+    let token = parseTokenResponse(include_str!("../test-data/reddit-token.json"))
+        .unwrap();
+    assert_eq!(token.access_token, "-fake-token");
+    assert_eq!(token.expires_in, Some(86400));
+    assert!(token.refresh_token.is_none());
+    assert!(parseTokenResponse(r#"{"error": "invalid_grant"}"#).is_err());
+}
+
+/// The access token saved on disk between runs.
+#[derive(Serialize, Deserialize)]
+struct TokenCache
+{
+    access_token: String,
+    refresh_token: Option<String>,
+    /// Timestamp of when the token expires.
+    expires_at: i64,
+}
+
+impl TokenCache
+{
+    fn load(filename: &str) -> Result<Self, Error>
+    {
+        let contents = fs::read_to_string(filename).map_err(
+            |_| error!(RedditError, format!("Failed to read file {}", filename)))?;
+        serde_json::from_str(&contents).map_err(
+            |_| error!(RedditError, format!("Failed to parse file {}", filename)))
+    }
+
+    fn save(&self, filename: &str) -> Result<(), Error>
+    {
+        let contents = serde_json::to_string(self).map_err(
+            |_| error!(RedditError, "Failed to generate token cache"))?;
+        fs::write(filename, contents).map_err(
+            |_| error!(RedditError, format!("Failed to write file {}", filename)))
+    }
+}
+
+/// Where the Reddit API is. These only need to change to talk to a
+/// mock server.
+#[derive(Clone)]
+pub struct Endpoints
+{
+    /// Base of the authorization and token URLs.
+    pub auth_base: String,
+    /// Base of the OAuth API URLs.
+    pub api_base: String,
+}
+
+impl Default for Endpoints
+{
+    fn default() -> Self
+    {
+        Self {
+            auth_base: "https://www.reddit.com".to_owned(),
+            api_base: "https://oauth.reddit.com".to_owned(),
+        }
+    }
+}
+
 /// Provide Reddit API access.
 ///
 /// To create a value of this type, use the authentication factories.
 #[allow(dead_code)]
 pub struct RedditQuerier
 {
-    token: String,
+    token: Mutex<String>,
     refresh_token: Option<String>,
     client_id: String,
     client_secret: String,
+    /// Where the token is cached, if it is.
+    cache_file: Option<String>,
+    endpoints: Endpoints,
 }
 
 impl RedditQuerier
 {
-    const URL_BASE: &'static str = "https://oauth.reddit.com";
     const USER_AGENT: &'static str = "desktop:org.darksair.keybot:0.0.1 (by /u/darksair)";
+    /// Number of times to send a query before giving up.
+    const MAX_TRIES: u32 = 3;
 
-    /// Make a [user-less
-    /// authentication](https://github.com/reddit-archive/reddit/wiki/OAuth2#application-only-oauth).
-    pub async fn fromUserlessAuthentication(
-        client_id: &str, client_secret: &str) -> Result<Self, Error>
+    /// Request an access token with `payload`.
+    async fn requestToken(endpoints: &Endpoints, client_id: &str,
+                          client_secret: &str, payload: &[(&str, &str)])
+                          -> Result<TokenResponse, Error>
     {
-        let url = "https://www.reddit.com/api/v1/access_token";
-        let payload = [("grant_type", "client_credentials"),];
+        let url = endpoints.auth_base.clone() + "/api/v1/access_token";
         let client = reqwest::Client::new();
-        let res = match client.post(url).header("User-Agent", Self::USER_AGENT)
-            .form(&payload).basic_auth(client_id, Some(client_secret))
+        let res = match client.post(&url).header("User-Agent", Self::USER_AGENT)
+            .form(payload).basic_auth(client_id, Some(client_secret))
             .send().await
         {
             Err(_) => { return Err(error!(
-                RedditError, "Failed to authenticate userless (post)")); },
+                RedditError, "Failed to request token (post)")); },
             Ok(res) => res,
         };
 
         if let Err(e) = res.error_for_status_ref()
         {
             return Err(error!(
-                RedditError, format!("Failed to authenticate userless ({})",
-                                     e.status().unwrap().as_u16())));
+                RedditError, format!("Failed to request token: {}", e)));
         }
 
-        let data: HashMap<String, serde_json::Value> = res.json().await.map_err(|e| {
-            error!(RedditError, format!("Failed to authenticate userless: {}", e))
+        let body = res.text().await.map_err(|e| {
+            error!(RedditError, format!("Failed to request token: {}", e))
         })?;
+        parseTokenResponse(&body)
+    }
+
+    /// Make a [user-less
+    /// authentication](https://github.com/reddit-archive/reddit/wiki/OAuth2#application-only-oauth).
+    #[allow(dead_code)]
+    pub async fn fromUserlessAuthentication(
+        client_id: &str, client_secret: &str, endpoints: Endpoints)
+        -> Result<Self, Error>
+    {
+        let token = Self::requestToken(
+            &endpoints, client_id, client_secret,
+            &[("grant_type", "client_credentials")]).await?;
 
         Ok(Self {
-            token: String::from(data["access_token"].as_str().unwrap()),
+            token: Mutex::new(token.access_token),
             refresh_token: None,
             client_id: client_id.to_owned(),
             client_secret: client_secret.to_owned(),
+            cache_file: None,
+            endpoints: endpoints,
         })
     }
 
+    /// Use the token cached in `cache_file` if it is not expired,
+    /// otherwise make a user-less authentication and cache the
+    /// token.
+    pub async fn fromCacheOrUserlessAuthentication(
+        client_id: &str, client_secret: &str, cache_file: &str,
+        endpoints: Endpoints) -> Result<Self, Error>
+    {
+        let mut querier = Self {
+            token: Mutex::new(String::new()),
+            refresh_token: None,
+            client_id: client_id.to_owned(),
+            client_secret: client_secret.to_owned(),
+            cache_file: Some(cache_file.to_owned()),
+            endpoints: endpoints,
+        };
+        match TokenCache::load(cache_file)
+        {
+            // Leave some time for the queries.
+            Ok(cache) if cache.expires_at > Utc::now().timestamp() + 60 =>
+            {
+                debug!("Using cached Reddit token.");
+                querier.token = Mutex::new(cache.access_token);
+                querier.refresh_token = cache.refresh_token;
+            },
+            _ => querier.reauthenticate().await?,
+        }
+        Ok(querier)
+    }
+
+    /// Get a new access token, with the refresh token if there is
+    /// one, and cache it if the querier uses a cache.
+    async fn reauthenticate(&self) -> Result<(), Error>
+    {
+        debug!("Getting new Reddit token...");
+        let token = if let Some(refresh) = &self.refresh_token
+        {
+            Self::requestToken(
+                &self.endpoints, &self.client_id, &self.client_secret,
+                &[("grant_type", "refresh_token"), ("refresh_token", refresh)])
+                .await?
+        }
+        else
+        {
+            Self::requestToken(&self.endpoints, &self.client_id,
+                               &self.client_secret,
+                               &[("grant_type", "client_credentials")]).await?
+        };
+
+        if let Some(cache_file) = &self.cache_file
+        {
+            let cache = TokenCache {
+                access_token: token.access_token.clone(),
+                refresh_token: self.refresh_token.clone(),
+                expires_at: Utc::now().timestamp() +
+                    token.expires_in.unwrap_or(3600),
+            };
+            if let Err(e) = cache.save(cache_file)
+            {
+                log_error!("{}", e);
+            }
+        }
+        *self.token.lock().unwrap() = token.access_token;
+        Ok(())
+    }
+
     /// Authenticate as a user. This requires a human to open the
     /// browser with a printed URI, which makes an HTTP request to a
     /// temperarary local HTTP server with the acquired credentials.
     #[allow(dead_code)]
-    pub fn fromAuthentication(client_id: &str, client_secret: &str)
-                              -> Result<Self, Error>
+    pub fn fromAuthentication(client_id: &str, client_secret: &str,
+                              endpoints: Endpoints) -> Result<Self, Error>
     {
         let now = time::SystemTime::now().duration_since(time::UNIX_EPOCH)
             .map_err(|_| {error!(RedditError, "failed to get time")})?;
@@ -93,7 +280,8 @@ privatemessages read report save submit subscribe vote \
 wikiedit wikiread")];
 
         let perm_url = reqwest::Url::parse_with_params(
-            "https://www.reddit.com/api/v1/authorize", &payload_init).unwrap();
+            &(endpoints.auth_base.clone() + "/api/v1/authorize"), &payload_init)
+            .map_err(|_| error!(RedditError, "Invalid authorization URL"))?;
         println!("Please open the following URI in your browser:\n\n{}",
                  perm_url);
 
@@ -119,7 +307,7 @@ wikiedit wikiread")];
             ("redirect_uri", "http://localhost:31416/")];
 
         let client = requests::Client::new();
-        let res = client.post("https://www.reddit.com/api/v1/access_token")
+        let res = client.post(&(endpoints.auth_base.clone() + "/api/v1/access_token"))
             .header("User-Agent", Self::USER_AGENT)
             .form(&payload_token).basic_auth(client_id, Some(client_secret))
             .send().map_err(|_| {
@@ -128,22 +316,25 @@ wikiedit wikiread")];
         res.error_for_status_ref().map_err(|e| {
             error!(RedditError, format!("Failed to get token: {}", e))})?;
 
-        let data: HashMap<String, serde_json::Value> = res.json().map_err(|e| {
-            error!(RedditError, format!("Failed to parse token response: {}", e))
+        let body = res.text().map_err(|e| {
+            error!(RedditError, format!("Failed to read token response: {}", e))
         })?;
+        let token = parseTokenResponse(&body)?;
 
         Ok(Self {
-            token: String::from(data["access_token"].as_str().unwrap()),
-            refresh_token: Some(String::from(
-                data["refresh_token"].as_str().unwrap())),
+            token: Mutex::new(token.access_token),
+            refresh_token: Some(token.refresh_token.ok_or_else(
+                || error!(RedditError, "No refresh token in token response"))?),
             client_id: client_id.to_owned(),
             client_secret: client_secret.to_owned(),
+            cache_file: None,
+            endpoints: endpoints,
         })
     }
 
     // Transform `url_raw` to a form that is suitable to use for
     // Reddit’s listing API.
-    fn urlPreprocess(url_raw: &str) -> Result<String, Error>
+    fn urlPreprocess(&self, url_raw: &str) -> Result<String, Error>
     {
         let url = reqwest::Url::parse(&("http://localhost".to_owned() + url_raw))
             .map_err(|_| error!(
@@ -158,47 +349,133 @@ wikiedit wikiread")];
             result = url_raw.to_owned() + "&raw_json=1";
         }
 
-        if !url_raw.starts_with(Self::URL_BASE)
+        if !url_raw.starts_with(&self.endpoints.api_base)
         {
-            result = Self::URL_BASE.to_owned() + &result;
+            result = self.endpoints.api_base.clone() + &result;
         }
         Ok(result)
     }
 
+    /// If Reddit says there is no request left in the rate limit
+    /// window, wait until the window resets.
+    async fn waitForRateLimit(headers: &reqwest::header::HeaderMap)
+    {
+        let header = |name: &str| headers.get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<f64>().ok());
+        if let (Some(remaining), Some(reset)) =
+            (header("X-Ratelimit-Remaining"), header("X-Ratelimit-Reset"))
+        {
+            if remaining < 1.0
+            {
+                info!("Reddit rate limit reached, waiting {}s...", reset);
+                tokio::time::delay_for(time::Duration::from_secs(reset as u64))
+                    .await;
+            }
+        }
+    }
+
     /// Take a `reqwest::RequestBuilder` and add the appropriate
     /// headers to the request. Send the request and see if the
-    /// respond HTTP code is good.
+    /// respond HTTP code is good. The token is renewed if it is
+    /// expired or revoked, and the request is retried if Reddit is
+    /// having a bad time.
     pub async fn query(&self, req_builder: reqwest::RequestBuilder)
                        -> Result<reqwest::Response, Error>
     {
-        let mut result = req_builder.header("User-Agent", Self::USER_AGENT);
-        result = result.header(
-            "Authorization",
-            "bearer ".to_owned() + self.token.as_ref());
-        let res = result.send().await.map_err(
-            |e| {error!(RedditError,
-                        format!("Failed to send request: {}", e))})?;
-        res.error_for_status().map_err(
-            |e| {error!(RedditError, format!("Query failed: {}", e))})
+        let mut last_error = error!(RedditError, "Query not sent");
+        for attempt in 1..=Self::MAX_TRIES
+        {
+            if attempt > 1
+            {
+                let wait = 2u64.pow(attempt - 1);
+                debug!("Retrying Reddit query in {}s...", wait);
+                tokio::time::delay_for(time::Duration::from_secs(wait)).await;
+            }
+
+            let req = req_builder.try_clone().ok_or_else(
+                || error!(RedditError, "Failed to clone request"))?;
+            let token = self.token.lock().unwrap().clone();
+            let res = match req.header("User-Agent", Self::USER_AGENT)
+                .header("Authorization", "bearer ".to_owned() + &token)
+                .send().await
+            {
+                Ok(res) => res,
+                Err(e) =>
+                {
+                    last_error = error!(RedditError,
+                                        format!("Failed to send request: {}", e));
+                    continue;
+                },
+            };
+            Self::waitForRateLimit(res.headers()).await;
+
+            let status = res.status();
+            if status == reqwest::StatusCode::UNAUTHORIZED
+            {
+                info!("Reddit token is expired or revoked.");
+                self.reauthenticate().await?;
+            }
+            else if status == reqwest::StatusCode::TOO_MANY_REQUESTS ||
+                status.is_server_error()
+            {
+                debug!("Reddit query failed with {}.", status);
+            }
+            else
+            {
+                return res.error_for_status().map_err(
+                    |e| {error!(RedditError, format!("Query failed: {}", e))});
+            }
+            last_error = error!(RedditError,
+                                format!("Query failed with {}", status));
+        }
+        Err(last_error)
     }
 
     /// Revoke the access token and consume self.
+    #[allow(dead_code)]
     pub async fn logout(self) -> Result<(), Error>
     {
-        let payload = [("token", self.token.as_ref()),
+        let token = self.token.lock().unwrap().clone();
+        let payload = [("token", token.as_ref()),
                        ("token_type_hint", "access_token")];
         let client = reqwest::Client::new();
-        let res = client.post("https://www.reddit.com/api/v1/revoke_token")
+        let res = client.post(
+            &(self.endpoints.auth_base.clone() + "/api/v1/revoke_token"))
             .header("User-Agent", Self::USER_AGENT)
             .form(&payload).basic_auth(&self.client_id, Some(&self.client_secret))
             .send().await.map_err(|_| {
                 error!(RedditError, "Failed to post logout request")})?;
 
+        if let Some(cache_file) = &self.cache_file
+        {
+            // The cached token is revoked too.
+            let _ = fs::remove_file(cache_file);
+        }
         res.error_for_status().map_err(|e| {
             error!(RedditError, format!("Failed to logout: {}", e))}).map(|_| ())
     }
 }
 
+/// What a post contains.
+#[derive(Clone, PartialEq, Debug)]
+pub enum PostMedia
+{
+    /// URI to an image.
+    Image(String),
+    /// URIs to the images in a gallery, in order.
+    Gallery(Vec<String>),
+    /// URI to a video file. Videos hosted on Reddit have no sound in
+    /// this file.
+    Video(String),
+    /// URI to an animation, as an MP4 or GIF file.
+    Gif(String),
+    /// URI to something else.
+    Link(String),
+    /// A text post.
+    Text,
+}
+
 pub struct Post
 {
     pub title: String,
@@ -212,6 +489,16 @@ pub struct Post
     pub count_comments: u32,
     pub time_create: DateTime<Utc>,
     pub sub: String,
+    pub media: PostMedia,
+    /// Full name of the original post, if this is a crosspost.
+    pub crosspost_parent: Option<String>,
+    pub over_18: bool,
+    pub spoiler: bool,
+    pub link_flair_text: Option<String>,
+    pub upvote_ratio: f64,
+    /// Full name of the author, which does not change when the
+    /// username does.
+    pub author_fullname: Option<String>,
 }
 
 impl Post
@@ -231,6 +518,13 @@ impl Post
             count_comments: 0,
             time_create: Utc.timestamp(0, 0),
             sub: String::new(),
+            media: PostMedia::Text,
+            crosspost_parent: None,
+            over_18: false,
+            spoiler: false,
+            link_flair_text: None,
+            upvote_ratio: 1.0,
+            author_fullname: None,
         }
     }
 
@@ -269,10 +563,317 @@ impl fmt::Debug for Post
 
 makeIntEnum!
 {
-    PostSorting {hot, new,} with u8,
+    PostSorting {hot, new, top, rising, controversial,} with u8,
+    derive(Copy, Clone, PartialEq)
+}
+
+// Time range of the `top` and `controversial` listings.
+makeIntEnum!
+{
+    TimeRange {hour, day, week, month, year, all,} with u8,
     derive(Copy, Clone, PartialEq)
 }
 
+/// Return `uri` without the parts that differ between copies of the
+/// same media on Reddit and Imgur.
+pub fn canonicalUrl(uri: &str) -> String
+{
+    let url = match reqwest::Url::parse(uri)
+    {
+        Ok(url) => url,
+        Err(_) => return uri.to_owned(),
+    };
+    let mut host = url.host_str().unwrap_or("").to_lowercase();
+    if host.starts_with("www.")
+    {
+        host = host["www.".len()..].to_owned();
+    }
+    let mut path = url.path().to_owned();
+    match host.as_str()
+    {
+        // Previews are resized copies of the images.
+        "preview.redd.it" | "external-preview.redd.it" =>
+        {
+            host = "i.redd.it".to_owned();
+        },
+        // Only the first part of the path is the video; the rest is
+        // the file of a certain quality.
+        "v.redd.it" =>
+        {
+            path = path.split('/').take(2).collect::<Vec<_>>().join("/");
+        },
+        // The same image or animation in different formats.
+        "i.imgur.com" | "imgur.com" =>
+        {
+            host = "i.imgur.com".to_owned();
+            if let Some(dot) = path.rfind('.')
+            {
+                path.truncate(dot);
+            }
+        },
+        _ => (),
+    }
+    format!("{}{}", host, path)
+}
+
+#[test]
+fn testCanonicalUrl()
+{
+    assert_eq!(canonicalUrl("https://preview.redd.it/abc.jpg?width=640&s=xyz"),
+               "i.redd.it/abc.jpg");
+    assert_eq!(canonicalUrl("https://i.redd.it/abc.jpg"), "i.redd.it/abc.jpg");
+    assert_eq!(canonicalUrl("https://v.redd.it/abc/DASH_720.mp4?source=fallback"),
+               "v.redd.it/abc");
+    assert_eq!(canonicalUrl("https://i.imgur.com/abc.gifv"),
+               canonicalUrl("http://imgur.com/abc.mp4"));
+}
+
+/// Get the URIs to the images in a gallery post from the post data
+/// in a listing.
+fn galleryFromData(data: &serde_json::Value) -> Vec<String>
+{
+    if data["is_gallery"].as_bool() != Some(true)
+    {
+        return Vec::new();
+    }
+    let items = match data["gallery_data"]["items"].as_array()
+    {
+        Some(items) => items,
+        None => return Vec::new(),
+    };
+    items.iter().filter_map(|item| {
+        let meta = &data["media_metadata"][item["media_id"].as_str()?];
+        if meta["status"].as_str() != Some("valid")
+        {
+            return None;
+        }
+        meta["s"]["u"].as_str().map(|u| u.to_owned())
+    }).collect()
+}
+
+/// Get the media in a video from the `reddit_video` object in the post
+/// data.
+fn redditVideo(video: &serde_json::Value) -> Option<PostMedia>
+{
+    let uri = video["fallback_url"].as_str()?.to_owned();
+    if video["is_gif"].as_bool() == Some(true)
+    {
+        Some(PostMedia::Gif(uri))
+    }
+    else
+    {
+        Some(PostMedia::Video(uri))
+    }
+}
+
+/// Find out the media in a post from the post data in a listing.
+fn mediaFromData(data: &serde_json::Value) -> PostMedia
+{
+    let gallery = galleryFromData(data);
+    if !gallery.is_empty()
+    {
+        return PostMedia::Gallery(gallery);
+    }
+    if data["is_self"].as_bool() == Some(true)
+    {
+        return PostMedia::Text;
+    }
+    if let Some(media) = redditVideo(&data["media"]["reddit_video"])
+    {
+        return media;
+    }
+
+    let link = data["url"].as_str().unwrap_or("").to_owned();
+    let link_path = link.split('?').next().unwrap();
+    let path = link_path.to_lowercase();
+    if path.ends_with(".jpg") || path.ends_with(".jpeg") || path.ends_with(".png")
+    {
+        return PostMedia::Image(link);
+    }
+    if path.ends_with(".gif")
+    {
+        return PostMedia::Gif(link);
+    }
+    // Imgur serves the same animation as MP4. Cut the extension from
+    // the original path, whose length may differ from the lowercased
+    // one.
+    let stem_len = link_path.len().saturating_sub(".gifv".len());
+    if let Some(ext) = link_path.get(stem_len..)
+    {
+        if ext.eq_ignore_ascii_case(".gifv")
+        {
+            return PostMedia::Gif(link_path[..stem_len].to_owned() + ".mp4");
+        }
+    }
+
+    // Animations and videos hosted elsewhere may have a preview on
+    // Reddit.
+    let preview = &data["preview"];
+    if let Some(media) = redditVideo(&preview["reddit_video_preview"])
+    {
+        return media;
+    }
+    if let Some(uri) = preview["images"][0]["variants"]["mp4"]["source"]["url"].as_str()
+    {
+        return PostMedia::Gif(uri.to_owned());
+    }
+    PostMedia::Link(link)
+}
+
+#[test]
+fn testMediaFromData()
+{
+    let image = serde_json::json!({"url": "https://i.redd.it/abc.JPG"});
+    assert_eq!(mediaFromData(&image),
+               PostMedia::Image("https://i.redd.it/abc.JPG".to_owned()));
+    let gifv = serde_json::json!({"url": "https://i.imgur.com/abc.gifv"});
+    assert_eq!(mediaFromData(&gifv),
+               PostMedia::Gif("https://i.imgur.com/abc.mp4".to_owned()));
+    let gifv = serde_json::json!({"url": "https://i.imgur.com/\u{130}abc.GIFV?x=1"});
+    assert_eq!(mediaFromData(&gifv),
+               PostMedia::Gif("https://i.imgur.com/\u{130}abc.mp4".to_owned()));
+    let video = serde_json::json!({
+        "url": "https://v.redd.it/abc",
+        "is_video": true,
+        "media": {"reddit_video": {
+            "fallback_url": "https://v.redd.it/abc/DASH_720.mp4",
+            "is_gif": false}}});
+    assert_eq!(mediaFromData(&video),
+               PostMedia::Video("https://v.redd.it/abc/DASH_720.mp4".to_owned()));
+    let text = serde_json::json!({"url": "https://www.reddit.com/r/a/comments/b/",
+                                  "is_self": true});
+    assert_eq!(mediaFromData(&text), PostMedia::Text);
+}
+
+#[derive(Deserialize)]
+struct Listing
+{
+    data: ListingData,
+}
+
+#[derive(Deserialize)]
+struct ListingData
+{
+    #[serde(default)]
+    children: Vec<Thing>,
+    after: Option<String>,
+}
+
+/// An item in a listing. A post is a thing of kind "t3".
+#[derive(Deserialize)]
+struct Thing
+{
+    kind: String,
+    data: serde_json::Value,
+}
+
+/// The fields of a post used in `Post`. Only `name` is required,
+/// because Reddit may leave any other field as null, e.g. for removed
+/// posts.
+#[derive(Deserialize)]
+struct PostData
+{
+    name: String,
+    title: Option<String>,
+    selftext: Option<String>,
+    author: Option<String>,
+    score: Option<i64>,
+    permalink: Option<String>,
+    url: Option<String>,
+    hide_score: Option<bool>,
+    num_comments: Option<u64>,
+    created_utc: Option<f64>,
+    subreddit: Option<String>,
+    crosspost_parent: Option<String>,
+    over_18: Option<bool>,
+    spoiler: Option<bool>,
+    link_flair_text: Option<String>,
+    upvote_ratio: Option<f64>,
+    author_fullname: Option<String>,
+}
+
+fn postFromData(data: &serde_json::Value, context: &str) -> Result<Post, Error>
+{
+    let post: PostData = serde_json::from_value(data.clone()).map_err(
+        |e| error!(RedditError, format!("Invalid post in listing from {}: {}",
+                                        context, e)))?;
+    Ok(Post {
+        title: post.title.unwrap_or_default(),
+        text: post.selftext.unwrap_or_default(),
+        author: post.author.unwrap_or_else(|| "[deleted]".to_owned()),
+        score: post.score.unwrap_or(0) as i32,
+        url: post.permalink.unwrap_or_default(),
+        link: post.url.unwrap_or_default(),
+        hide_score: post.hide_score.unwrap_or(false),
+        count_comments: post.num_comments.unwrap_or(0) as u32,
+        time_create: Utc.timestamp(post.created_utc.unwrap_or(0.0) as i64, 0),
+        sub: post.subreddit.unwrap_or_default(),
+        media: mediaFromData(data),
+        crosspost_parent: post.crosspost_parent,
+        over_18: post.over_18.unwrap_or(false),
+        spoiler: post.spoiler.unwrap_or(false),
+        link_flair_text: post.link_flair_text,
+        upvote_ratio: post.upvote_ratio.unwrap_or(1.0),
+        author_fullname: post.author_fullname,
+        id: post.name,
+    })
+}
+
+/// Parse the body of a listing response. Return the posts in the
+/// listing, and the full name of the thing to list after.
+/// `context` describes the listing in the error messages.
+fn parseListing(body: &str, context: &str)
+                -> Result<(Vec<Post>, Option<String>), Error>
+{
+    checkErrorResponse(body, context)?;
+    let listing: Listing = serde_json::from_str(body).map_err(
+        |e| error!(RedditError, format!("Invalid listing from {}: {}",
+                                        context, e)))?;
+    let posts = listing.data.children.iter().filter(|thing| thing.kind == "t3")
+        .map(|thing| postFromData(&thing.data, context))
+        .collect::<Result<Vec<Post>, Error>>()?;
+    Ok((posts, listing.data.after))
+}
+
+#[test]
+fn testParseListing()
+{
+    let (posts, after) = parseListing(
+        include_str!("../test-data/reddit-listing.json"), "test").unwrap();
+    assert_eq!(after.as_deref(), Some("t3_abc002"));
+    assert_eq!(posts.len(), 2);
+    assert_eq!(posts[0].fullName(), "t3_abc001");
+    assert_eq!(posts[0].score, 1234);
+    assert_eq!(posts[0].time_create, Utc.timestamp(1600000000, 0));
+    assert_eq!(posts[0].media,
+               PostMedia::Image("https://i.redd.it/abc001.jpg".to_owned()));
+    assert_eq!(posts[1].media, PostMedia::Gallery(vec![
+        "https://preview.redd.it/img2.jpg?width=4032".to_owned(),
+        "https://preview.redd.it/img1.jpg?width=4032".to_owned()]));
+}
+
+#[test]
+fn testParseListingWithNulls()
+{
+    let (posts, after) = parseListing(
+        include_str!("../test-data/reddit-listing-nulls.json"), "test").unwrap();
+    assert!(after.is_none());
+    // The comment is skipped.
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0].author, "[deleted]");
+    assert_eq!(posts[0].text, "");
+    assert!(!posts[0].hide_score);
+    assert_eq!(posts[0].media, PostMedia::Text);
+}
+
+#[test]
+fn testParseErrorListing()
+{
+    assert!(parseListing(include_str!("../test-data/reddit-error.json"), "test")
+            .is_err());
+    assert!(parseListing("not json", "test").is_err());
+}
+
 pub struct Subreddit
 {
     name: String
@@ -290,11 +891,20 @@ impl Subreddit
         "/r/".to_owned() + &self.name
     }
 
+    /// List a page of posts. `time_range` only matters to the `top`
+    /// and `controversial` sortings. Return the posts and the cursor
+    /// to the next page.
     pub async fn list(&self, querier: &RedditQuerier, sorting: PostSorting,
+                      time_range: Option<TimeRange>,
                       before: Option<&str>, after: Option<&str>)
-                      -> Result<Vec<Post>, Error>
+                      -> Result<(Vec<Post>, Option<String>), Error>
     {
-        let mut query = vec![("g", "GLOBAL"),];
+        let mut query = vec![("g", "GLOBAL"), ("limit", "100")];
+        let time_range = time_range.map(|t| t.to_string());
+        if let Some(t) = &time_range
+        {
+            query.push(("t", t));
+        }
         if let Some(before_id) = before
         {
             query.push(("before", before_id));
@@ -306,30 +916,71 @@ impl Subreddit
 
         let client = reqwest::Client::new();
         let res = querier.query(
-            client.get(&RedditQuerier::urlPreprocess(
+            client.get(&querier.urlPreprocess(
                 &format!("{}/{}.json", self.urlName(), sorting))?)
                 .query(&query)).await?;
-        let data: serde_json::Value = res.json().await.map_err(
-            |_| {error!(RedditError, "Invalid JSON in listing")})?;
-        // println!("{}", serde_json::to_string_pretty(&data).unwrap());
-        Ok(data["data"]["children"].as_array().unwrap().iter().map(
-            |post_data_wrapper|
-            {
-                let data = &post_data_wrapper["data"];
-                Post {
-                    title: data["title"].as_str().unwrap().to_owned(),
-                    text: data["selftext"].as_str().unwrap().to_owned(),
-                    author: data["author"].as_str().unwrap().to_owned(),
-                    score: data["score"].as_i64().unwrap() as i32,
-                    url: data["permalink"].as_str().unwrap().to_owned(),
-                    link: data["url"].as_str().unwrap().to_owned(),
-                    hide_score: data["hide_score"].as_bool().unwrap(),
-                    id: data["name"].as_str().unwrap().to_owned(),
-                    count_comments: data["num_comments"].as_u64().unwrap() as u32,
-                    time_create: Utc.timestamp(
-                        data["created_utc"].as_f64().unwrap() as i64, 0),
-                    sub: data["subreddit"].as_str().unwrap().to_owned(),
-                }
-            }).collect())
+        let body = res.text().await.map_err(
+            |e| {error!(RedditError, format!("Failed to read listing: {}", e))})?;
+        parseListing(&body, &self.urlName())
     }
+
+    /// Fetch the page after the cursor in `state`, and work out where
+    /// the next page starts.
+    async fn nextPage(&self, querier: &RedditQuerier, sorting: PostSorting,
+                      time_range: Option<TimeRange>,
+                      cutoff: Option<DateTime<Utc>>, state: PageState)
+                      -> Result<Option<(Vec<Post>, PageState)>, Error>
+    {
+        if state.finished
+        {
+            return Ok(None);
+        }
+        let (mut posts, after) = self.list(
+            querier, sorting, time_range, None, state.after.as_deref()).await?;
+        let mut finished = posts.is_empty() || after.is_none() ||
+            after == state.after || state.pages_left <= 1;
+        if let Some(time) = cutoff
+        {
+            posts.retain(|p| p.time_create >= time);
+            // A page with nothing new enough is the end of a
+            // chronological listing.
+            finished = finished || posts.is_empty();
+        }
+        Ok(Some((posts, PageState {
+            after: after,
+            pages_left: state.pages_left.saturating_sub(1),
+            finished: finished,
+        })))
+    }
+
+    /// Stream the posts in the listing, following the `after` cursors
+    /// for at most `max_pages` pages. If `cutoff` is given, posts
+    /// created before it are skipped, and the stream ends after a page
+    /// with none after it.
+    pub fn stream<'a>(&'a self, querier: &'a RedditQuerier,
+                      sorting: PostSorting, time_range: Option<TimeRange>,
+                      max_pages: u32, cutoff: Option<DateTime<Utc>>)
+                      -> impl Stream<Item = Result<Post, Error>> + 'a
+    {
+        let state = PageState {
+            after: None,
+            pages_left: max_pages,
+            finished: max_pages == 0,
+        };
+        stream::try_unfold(state, move |state| async move {
+            let page = self.nextPage(querier, sorting, time_range, cutoff,
+                                     state).await;
+            page.map(|page| page.map(|(posts, state)| {
+                (stream::iter(posts.into_iter().map(Ok)), state)
+            }))
+        }).try_flatten()
+    }
+}
+
+/// Where a stream of a listing is at.
+struct PageState
+{
+    after: Option<String>,
+    pages_left: u32,
+    finished: bool,
 }
@@ -0,0 +1,146 @@
+// A local HTTP server that pretends to be Reddit, with the recorded
+// responses in test-data. Only for tests.
+
+use std::net::{TcpListener, TcpStream};
+use std::io::{Read, Write};
+use std::thread;
+
+use chrono::prelude::*;
+
+use crate::reddit;
+
+const EMPTY_LISTING: &str =
+    r#"{"kind": "Listing", "data": {"after": null, "children": []}}"#;
+
+pub struct MockReddit
+{
+    port: u16,
+}
+
+impl MockReddit
+{
+    /// Start serving on a free port in the background.
+    pub fn start() -> Self
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming()
+            {
+                if let Ok(stream) = stream
+                {
+                    handleConnection(stream);
+                }
+            }
+        });
+        Self { port: port }
+    }
+
+    pub fn endpoints(&self) -> reddit::Endpoints
+    {
+        let base = format!("http://127.0.0.1:{}", self.port);
+        reddit::Endpoints {
+            auth_base: base.clone(),
+            api_base: base,
+        }
+    }
+}
+
+fn contentLength(head: &str) -> usize
+{
+    head.lines().filter_map(|line| {
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next()?;
+        let value = parts.next()?;
+        if name.eq_ignore_ascii_case("content-length")
+        {
+            value.trim().parse().ok()
+        }
+        else
+        {
+            None
+        }
+    }).next().unwrap_or(0)
+}
+
+/// Read the whole request and return the head of it.
+fn readRequest(stream: &mut TcpStream) -> String
+{
+    let mut data: Vec<u8> = Vec::new();
+    let mut buffer = [0; 4096];
+    let mut head_len: Option<usize> = None;
+    let mut body_len = 0;
+    loop
+    {
+        if let Some(len) = head_len
+        {
+            if data.len() >= len + body_len
+            {
+                break;
+            }
+        }
+        let n = match stream.read(&mut buffer)
+        {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        data.extend_from_slice(&buffer[..n]);
+        if head_len.is_none()
+        {
+            if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n")
+            {
+                head_len = Some(pos + 4);
+                body_len = contentLength(&String::from_utf8_lossy(&data[..pos]));
+            }
+        }
+    }
+    let end = head_len.unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+/// The recorded listing, with the posts made an hour ago.
+fn freshListing() -> String
+{
+    let mut listing: serde_json::Value = serde_json::from_str(
+        include_str!("../test-data/reddit-listing.json")).unwrap();
+    let time = (Utc::now().timestamp() - 3600) as f64;
+    for child in listing["data"]["children"].as_array_mut().unwrap()
+    {
+        child["data"]["created_utc"] = serde_json::json!(time);
+    }
+    listing.to_string()
+}
+
+fn respond(path: &str) -> (&'static str, String)
+{
+    if path.starts_with("/api/v1/access_token")
+    {
+        ("200 OK", include_str!("../test-data/reddit-token.json").to_owned())
+    }
+    else if path.starts_with("/r/") && path.contains("after=")
+    {
+        // There is only one page.
+        ("200 OK", EMPTY_LISTING.to_owned())
+    }
+    else if path.starts_with("/r/")
+    {
+        ("200 OK", freshListing())
+    }
+    else
+    {
+        ("404 Not Found", include_str!("../test-data/reddit-error.json")
+         .to_owned())
+    }
+}
+
+fn handleConnection(mut stream: TcpStream)
+{
+    let request = readRequest(&mut stream);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    let (status, body) = respond(path);
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body);
+    let _ = stream.write_all(response.as_bytes());
+}
@@ -0,0 +1,332 @@
+use std::time;
+use std::collections::{BTreeMap, BTreeSet};
+
+use log::{info,debug};
+use log::error as log_error;
+use tokio;
+use regex::Regex;
+use telegram_bot as bot;
+use telegram_bot::types::Message;
+use chrono::prelude::*;
+
+use crate::error::Error;
+use crate::bot_config;
+use crate::i18n;
+use crate::telegram;
+use crate::chat_db;
+use crate::reddit;
+use crate::keybot;
+
+/// Seen posts older than this are forgotten. New posts are long gone
+/// from the listing by then.
+const SEEN_DAYS: i64 = 7;
+
+/// A watch rule with its title regex compiled.
+struct Rule<'a>
+{
+    config: &'a bot_config::WatchRule,
+    title: Option<Regex>,
+}
+
+fn compileRules(rules: &[bot_config::WatchRule]) -> Result<Vec<Rule>, Error>
+{
+    rules.iter().map(|rule| {
+        let title = match &rule.title
+        {
+            Some(pattern) => Some(Regex::new(pattern).map_err(
+                |e| error!(RuntimeError, format!(
+                    "Invalid title regex in watch rule {}: {}", rule.name, e)))?),
+            None => None,
+        };
+        Ok(Rule { config: rule, title: title })
+    }).collect()
+}
+
+fn matchesRule(rule: &Rule, post: &reddit::Post) -> bool
+{
+    if !rule.config.subreddit.eq_ignore_ascii_case(&post.sub)
+    {
+        return false;
+    }
+    if let Some(pattern) = &rule.title
+    {
+        if !pattern.is_match(&post.title)
+        {
+            return false;
+        }
+    }
+    if let Some(flair) = &rule.config.flair
+    {
+        if !flair.eq_ignore_ascii_case(post.link_flair_text.as_deref().unwrap_or(""))
+        {
+            return false;
+        }
+    }
+    true
+}
+
+#[test]
+fn testMatchesRule()
+{
+    let configs = vec![
+        bot_config::WatchRule {
+            name: "gb".to_owned(),
+            subreddit: "mk".to_owned(),
+            title: Some(r"(?i)group ?buy|\bGB\b|\bIC\b".to_owned()),
+            flair: None,
+        },
+        bot_config::WatchRule {
+            name: "giveaway".to_owned(),
+            subreddit: "MechanicalKeyboards".to_owned(),
+            title: None,
+            flair: Some("Giveaway".to_owned()),
+        },
+    ];
+    let rules = compileRules(&configs).unwrap();
+    let mut post = reddit::Post::new();
+    post.sub = "MK".to_owned();
+    post.title = "[GB] GMK Olivia".to_owned();
+    assert!(matchesRule(&rules[0], &post));
+    post.title = "Groupbuy of a new case".to_owned();
+    assert!(matchesRule(&rules[0], &post));
+    post.title = "My GBA build".to_owned();
+    assert!(!matchesRule(&rules[0], &post));
+
+    post.sub = "mechanicalkeyboards".to_owned();
+    assert!(!matchesRule(&rules[1], &post));
+    post.link_flair_text = Some("giveaway".to_owned());
+    assert!(matchesRule(&rules[1], &post));
+}
+
+fn alertText(config: &bot_config::ConfigParams, rule: &str, post: &reddit::Post)
+             -> Result<String, Error>
+{
+    let format = config.general.text_format;
+    let esc = |text: &str| telegram::escape(text, format);
+    Ok(i18n::template(config, "watch_template", None, None)?
+       .apply("rule", esc(rule)).apply("title", esc(&post.title))
+       .apply("author", esc(&post.author))
+       .apply("flair", esc(post.link_flair_text.as_deref().unwrap_or("")))
+       .apply("subreddit", esc(&post.sub)).apply("url", esc(&post.shortUrl()))
+       .result())
+}
+
+/// Send an alert about the post to the chat in the config, and to the
+/// users subscribed to any of the rules. Fail if no chat receives it,
+/// so that it is tried again.
+async fn sendAlert(api: &bot::Api, config: &bot_config::ConfigParams,
+                   watch: &bot_config::ConfigParamsWatch, rules: &[&Rule<'_>],
+                   post: &reddit::Post) -> Result<(), Error>
+{
+    let mut chats: BTreeSet<i64> = BTreeSet::new();
+    chats.extend(watch.chat_id);
+    for rule in rules
+    {
+        chats.extend(chat_db::watchSubscribers(&rule.config.name)?);
+    }
+    info!("Post {} matches watch rule {}.", post.id, rules[0].config.name);
+    let text = alertText(config, &rules[0].config.name, post)?;
+    // With nobody to alert, there is nothing to try again.
+    let mut sent = chats.is_empty();
+    for chat_id in chats
+    {
+        match telegram::sendFormatted(
+            api, bot::types::ChatId::new(chat_id), &text,
+            config.general.text_format, None).await
+        {
+            Ok(_) => sent = true,
+            Err(e) => log_error!("Failed to send alert to {}: {}", chat_id, e),
+        }
+    }
+    if sent
+    {
+        Ok(())
+    }
+    else
+    {
+        Err(error!(RuntimeError, format!("No chat received the alert about {}",
+                                         post.id)))
+    }
+}
+
+/// Alert about the post if it is not seen yet, and then mark it seen.
+/// A post that fails to alert about is tried again next time. A seen
+/// post is never alerted about again, even to rules or subscribers
+/// added later.
+async fn alertIfNew(api: &bot::Api, config: &bot_config::ConfigParams,
+                    watch: &bot_config::ConfigParamsWatch, rules: &[&Rule<'_>],
+                    post: &reddit::Post, now: DateTime<Utc>) -> Result<(), Error>
+{
+    if chat_db::isRedditSeen(&post.id)?
+    {
+        return Ok(());
+    }
+    sendAlert(api, config, watch, rules, post).await?;
+    chat_db::markRedditSeen(&post.id, now)
+}
+
+/// Check the new posts in the subreddits of the rules, and alert
+/// about the ones that match. Posts created before `since` are
+/// ignored.
+async fn checkNewPosts(api: &bot::Api, config: &bot_config::ConfigParams,
+                       watch: &bot_config::ConfigParamsWatch,
+                       redditor: &reddit::RedditQuerier, rules: &[Rule<'_>],
+                       since: DateTime<Utc>) -> Result<(), Error>
+{
+    let mut rules_by_sub: BTreeMap<String, Vec<&Rule>> = BTreeMap::new();
+    for rule in rules
+    {
+        rules_by_sub.entry(rule.config.subreddit.to_lowercase())
+            .or_insert_with(Vec::new).push(rule);
+    }
+
+    let now = Utc::now();
+    for (sub_name, sub_rules) in &rules_by_sub
+    {
+        let sub = reddit::Subreddit::new(sub_name);
+        let (posts, _) = match sub.list(redditor, reddit::PostSorting::new,
+                                        None, None, None).await
+        {
+            Ok(page) => page,
+            Err(e) =>
+            {
+                log_error!("Failed to get new posts from r/{}: {}", sub_name, e);
+                continue;
+            },
+        };
+        for post in posts.iter().filter(|p| p.time_create >= since)
+        {
+            let matched: Vec<&Rule> = sub_rules.iter().cloned()
+                .filter(|rule| matchesRule(rule, post)).collect();
+            if matched.is_empty()
+            {
+                continue;
+            }
+            if let Err(e) = alertIfNew(api, config, watch, &matched, post, now).await
+            {
+                log_error!("Failed to alert about post {}: {}", post.id, e);
+            }
+        }
+    }
+    chat_db::pruneRedditSeen(now - chrono::Duration::days(SEEN_DAYS))
+}
+
+/// Check for new posts matching the watch rules every
+/// `interval_sec`, until the bot stops.
+pub fn start(api: &bot::Api, config: &bot_config::ConfigParams)
+{
+    let api = api.clone();
+    let config = config.clone();
+    tokio::spawn(async move {
+        let watch = match &config.reddit.watch
+        {
+            Some(watch) => watch,
+            None => return,
+        };
+        let rules = match compileRules(&watch.rules)
+        {
+            Ok(rules) => rules,
+            Err(e) => { log_error!("{}", e); return; },
+        };
+        let interval = time::Duration::from_secs(watch.interval_sec);
+        let mut redditor = None;
+        // Only alert about posts made since shortly before the bot
+        // started, so that a restart does not flood the chats.
+        let since = Utc::now() - chrono::Duration::seconds(
+            watch.interval_sec as i64);
+        loop
+        {
+            if redditor.is_none()
+            {
+                debug!("Authenticating on Reddit...");
+                match reddit::RedditQuerier::fromCacheOrUserlessAuthentication(
+                    &config.reddit.client_id, &config.reddit.client_secret,
+                    &config.reddit.token_cache,
+                    keybot::redditEndpoints(&config.reddit)).await
+                {
+                    Ok(r) => redditor = Some(r),
+                    Err(e) => log_error!("{}", e),
+                }
+            }
+            if let Some(r) = &redditor
+            {
+                if let Err(e) = checkNewPosts(&api, &config, watch, r, &rules,
+                                              since).await
+                {
+                    log_error!("{}", e);
+                }
+            }
+            tokio::time::delay_for(interval).await;
+        }
+    });
+}
+
+/// The names of the rules that `args` select, or of all the rules if
+/// `args` is empty.
+fn selectRules<'a>(watch: &'a bot_config::ConfigParamsWatch, args: &[&str])
+                   -> Result<Vec<&'a str>, Error>
+{
+    if args.is_empty()
+    {
+        return Ok(watch.rules.iter().map(|r| r.name.as_str()).collect());
+    }
+    args.iter().map(|arg| {
+        watch.rules.iter().find(|r| r.name.eq_ignore_ascii_case(arg))
+            .map(|r| r.name.as_str()).ok_or_else(
+                || error!(RuntimeError, format!("Unknown watch rule: {}", arg)))
+    }).collect()
+}
+
+/// Subscribe or unsubscribe the user to the alerts of the rules in
+/// `args`, or of all the rules. Only works in a private chat.
+pub async fn onSubscribeCommand(api: &bot::Api, config: &bot_config::ConfigParams,
+                                msg: &Message, args: &[&str], subscribe: bool)
+                                -> Result<(), Error>
+{
+    let watch = match &config.reddit.watch
+    {
+        Some(watch) => watch,
+        None => return Ok(()),
+    };
+    match msg.chat
+    {
+        bot::types::MessageChat::Private(_) => (),
+        _ =>
+        {
+            debug!("Subscription is only in private chats.");
+            return Ok(());
+        },
+    }
+
+    let user_id = i64::from(msg.from.id);
+    let names = selectRules(watch, args)?;
+    let tplt_key = if subscribe
+    {
+        for name in &names
+        {
+            chat_db::addWatchSubscription(user_id, name)?;
+        }
+        "watch_subscribed_template"
+    }
+    else
+    {
+        if args.is_empty()
+        {
+            chat_db::removeWatchSubscription(user_id, None)?;
+        }
+        else
+        {
+            for name in &names
+            {
+                chat_db::removeWatchSubscription(user_id, Some(name))?;
+            }
+        }
+        "watch_unsubscribed_template"
+    };
+    let format = config.general.text_format;
+    let text = i18n::template(config, tplt_key, msg.from.language_code.as_deref(),
+                              None)?
+        .apply("rules", telegram::escape(&names.join(", "), format)).result();
+    telegram::sendFormatted(api, msg.chat.id(), &text, format, Some(msg.id)).await?;
+    Ok(())
+}
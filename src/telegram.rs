@@ -7,9 +7,14 @@ use log::{info,debug};
 use tokio;
 use telegram_bot as bot;
 use telegram_bot::types::Message;
-use telegram_bot::types::requests::{SendMessage, SendPhoto, GetChatMember};
+use telegram_bot::types::requests::{SendMessage, SendPhoto, GetChatMember,
+                                    GetChatAdministrators, ForwardMessage,
+                                    DeleteMessage, KickChatMember,
+                                    UnbanChatMember};
 use reqwest;
 use reqwest::header::CONTENT_LENGTH;
+use serde_json;
+use serde_json::json;
 use tempfile;
 
 use crate::error::Error;
@@ -58,6 +63,104 @@ pub fn getUsername(u: &bot::User) -> String
     }
 }
 
+/// Escape `text` so that it is displayed literally in a message with
+/// `format`.
+pub fn escape(text: &str, format: bot_config::TextFormat) -> String
+{
+    match format
+    {
+        bot_config::TextFormat::Plain => text.to_owned(),
+        bot_config::TextFormat::Html => text.replace('&', "&amp;")
+            .replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;"),
+        bot_config::TextFormat::MarkdownV2 =>
+        {
+            let mut result = String::with_capacity(text.len());
+            for c in text.chars()
+            {
+                if "_*[]()~`>#+-=|{}.!\\".contains(c)
+                {
+                    result.push('\\');
+                }
+                result.push(c);
+            }
+            result
+        },
+    }
+}
+
+#[test]
+fn testEscape()
+{
+    assert_eq!(escape("<b>&</b>", bot_config::TextFormat::Html),
+               "&lt;b&gt;&amp;&lt;/b&gt;");
+    assert_eq!(escape("a_b (c).", bot_config::TextFormat::MarkdownV2),
+               "a\\_b \\(c\\)\\.");
+    assert_eq!(escape("a_b", bot_config::TextFormat::Plain), "a_b");
+}
+
+/// Return a mention of `u` in a message with `format`. There is no
+/// way to mention a user without a username in plain text, so with
+/// `TextFormat::Plain` this is just the displayed name.
+pub fn mention(u: &bot::User, style: bot_config::MentionStyle,
+               format: bot_config::TextFormat) -> String
+{
+    let name = match style
+    {
+        bot_config::MentionStyle::Username => getUsername(u),
+        bot_config::MentionStyle::FullName => getUserFullname(u),
+    };
+    let id = i64::from(u.id);
+    match format
+    {
+        bot_config::TextFormat::Plain => name,
+        bot_config::TextFormat::Html => format!(
+            "<a href=\"tg://user?id={}\">{}</a>", id, escape(&name, format)),
+        bot_config::TextFormat::MarkdownV2 => format!(
+            "[{}](tg://user?id={})", escape(&name, format), id),
+    }
+}
+
+/// Return the parse mode of messages with `format`.
+pub fn getParseMode(format: bot_config::TextFormat) -> Option<bot::types::ParseMode>
+{
+    match format
+    {
+        bot_config::TextFormat::Plain => None,
+        bot_config::TextFormat::Html => Some(bot::types::ParseMode::Html),
+        bot_config::TextFormat::MarkdownV2 => Some(bot::types::ParseMode::MarkdownV2),
+    }
+}
+
+/// Send `text` to the chat with `format`.
+pub async fn sendFormatted(api: &bot::Api, chat_id: bot::ChatId, text: &str,
+                           format: bot_config::TextFormat,
+                           reply_to: Option<bot::MessageId>)
+                           -> Result<bot::types::MessageOrChannelPost, Error>
+{
+    let mut req = SendMessage::new(chat_id, text);
+    if let Some(mode) = getParseMode(format)
+    {
+        req.parse_mode(mode);
+    }
+    if let Some(id) = reply_to
+    {
+        req.reply_to(id);
+    }
+    api.send(req).await.map_err(
+        |_| error!(RuntimeError, "Failed to send formatted message"))
+}
+
+pub fn getChatTitle(chat: &bot::types::MessageChat) -> String
+{
+    match chat
+    {
+        bot::types::MessageChat::Private(u) => getUserFullname(u),
+        bot::types::MessageChat::Group(g) => g.title.clone(),
+        bot::types::MessageChat::Supergroup(g) => g.title.clone(),
+        _ => String::new(),
+    }
+}
+
 pub async fn getChatMember(api: &bot::Api, chat_id: i64, user_id: i64)
                      -> Result<bot::types::User, Error>
 {
@@ -67,20 +170,54 @@ pub async fn getChatMember(api: &bot::Api, chat_id: i64, user_id: i64)
     Ok(chat_member.user)
 }
 
+/// Is the user with `user_id` an administrator of the chat?
+pub async fn isChatAdmin(api: &bot::Api, chat_id: bot::ChatId, user_id: bot::UserId)
+                         -> Result<bool, Error>
+{
+    let admins = api.send(GetChatAdministrators::new(chat_id)).await.map_err(
+        |_| error!(RuntimeError, "Failed to get chat administrators"))?;
+    Ok(admins.iter().any(|member| member.user.id == user_id))
+}
+
 /// If `msg` is a reply to a message that is not a channel post,
-/// return the message id to which `msg` replies.
-pub fn getParentMsgId(msg: &Message) -> Option<bot::MessageId>
+/// return the message to which `msg` replies.
+pub fn getParentMsg(msg: &Message) -> Option<&Message>
 {
     if let Some(reply_to) = &msg.reply_to_message
     {
         if let bot::types::MessageOrChannelPost::Message(m) = reply_to.as_ref()
         {
-            return Some(m.id);
+            return Some(m);
         }
     }
     None
 }
 
+/// If `msg` is a reply to a message that is not a channel post,
+/// return the message id to which `msg` replies.
+pub fn getParentMsgId(msg: &Message) -> Option<bot::MessageId>
+{
+    getParentMsg(msg).map(|m| m.id)
+}
+
+/// Return the ID of a sent message, whether it is in a chat or a
+/// channel.
+pub fn getPostId(post: &bot::types::MessageOrChannelPost) -> bot::MessageId
+{
+    match post
+    {
+        bot::types::MessageOrChannelPost::Message(m) => m.id,
+        bot::types::MessageOrChannelPost::ChannelPost(p) => p.id,
+    }
+}
+
+/// Return the link to a message in a supergroup or a channel.
+pub fn getMsgLink(chat_id: i64, msg_id: i64) -> String
+{
+    let chat = chat_id.to_string();
+    format!("https://t.me/c/{}/{}", chat.trim_start_matches("-100"), msg_id)
+}
+
 fn parseMagickSizeOutput(output: &str) -> Result<(u32, u32), Error>
 {
     let mut parts = output.split('x');
@@ -174,67 +311,512 @@ async fn getUriFileSize(uri: &str) -> Result<UriFileInfo, Error>
     }
 }
 
-pub async fn sendPhoto(api: &bot::Api, uri: &str, caption: &str, chat_id: i64)
-                       -> Result<Message, Error>
+/// Name of the parse mode of `format` in the Bot API.
+fn parseModeName(format: bot_config::TextFormat) -> Option<&'static str>
 {
-    debug!("Sending photo at {}...", uri);
+    match format
+    {
+        bot_config::TextFormat::Plain => None,
+        bot_config::TextFormat::Html => Some("HTML"),
+        bot_config::TextFormat::MarkdownV2 => Some("MarkdownV2"),
+    }
+}
+
+async fn sendPhotoWithCaption(api: &bot::Api, mut req: SendPhoto<'_>,
+                              caption: &str, format: bot_config::TextFormat)
+                              -> Result<Message, Error>
+{
+    req.caption(caption);
+    if let Some(mode) = getParseMode(format)
+    {
+        req.parse_mode(mode);
+    }
+    api.send(req).await.map_err(|_| error!(RuntimeError, "Failed to send photo"))
+}
+
+/// A photo ready to send to Telegram.
+enum PhotoFile
+{
+    /// Telegram can fetch the photo from the URI.
+    Uri(String),
+    /// A local file to upload.
+    Local(String),
+    /// A temporary file to upload and then remove.
+    Temp(String),
+}
+
+impl PhotoFile
+{
+    fn remove(&self)
+    {
+        if let PhotoFile::Temp(f) = self
+        {
+            if let Err(e) = fs::remove_file(f)
+            {
+                debug!("Failed to remove temp file {}: {}", f, e);
+            }
+        }
+    }
+}
+
+/// Make the photo at `uri`, which is a URL or a local path, ready to
+/// send. A photo that is too large for Telegram is downloaded and
+/// resized.
+async fn preparePhoto(uri: &str) -> Result<PhotoFile, Error>
+{
+    let is_local = !uri.contains("://");
     let size = getImageSize(uri)?;
     debug!("Image size is {}x{}.", size.0, size.1);
-    let file_info = getUriFileSize(uri).await?;
+    let file_info = if is_local
+    {
+        UriFileInfo {
+            size: fs::metadata(uri).map_err(
+                |_| error!(RuntimeError, format!("Failed to get size of {}", uri)))?
+                .len(),
+            filename: None,
+        }
+    }
+    else
+    {
+        getUriFileSize(uri).await?
+    };
     // debug!("File size for {} is {}.", file_info.filename, file_info.size);
     if size.0 < bot_config::TG_IMG_SIZE_LIMIT &&
         size.1 < bot_config::TG_IMG_SIZE_LIMIT &&
         file_info.size < bot_config::TG_IMG_FILE_SIZE_LIMIT
     {
-        api.send(SendPhoto::new(bot::types::ChatId::new(chat_id),
-                                bot::types::InputFileRef::new(uri))
-                 .caption(caption)).await.map_err(
-            |_| error!(RuntimeError, "Failed to send photo"))
+        if let Some(f) = &file_info.filename
+        {
+            PhotoFile::Temp(f.clone()).remove();
+        }
+        return Ok(if is_local
+                  {
+                      PhotoFile::Local(uri.to_owned())
+                  }
+                  else
+                  {
+                      PhotoFile::Uri(uri.to_owned())
+                  });
+    }
+
+    info!("Processing large image file...");
+    // Download image.
+    let img_orig = if let Some(f) = &file_info.filename
+    {
+        PhotoFile::Temp(f.to_string())
+    }
+    else if is_local
+    {
+        PhotoFile::Local(uri.to_owned())
     }
     else
     {
-        info!("Processing large image file...");
-        // Download image.
-        let img_orig: String = if let Some(f) = &file_info.filename
+        let f = getTempFile(None)?.to_str().ok_or(
+            error!(RuntimeError, "Failed to encode temp file path"))?
+            .to_string();
+        downloadFile(uri, &f)?;
+        PhotoFile::Temp(f)
+    };
+    let img_orig_path = match &img_orig
+    {
+        PhotoFile::Uri(f) | PhotoFile::Local(f) | PhotoFile::Temp(f) => f.clone(),
+    };
+    // Resize
+    info!("Resizing image to {}...", bot_config::IMG_RESIZE_TARGET);
+    // Due to the limitation of the VPS, this is surprisingly easy to
+    // fail.
+    let img_resized = getTempFile(Some(".jpg"))?.to_str().ok_or(
+        error!(RuntimeError, "Failed to encode temp file path"))?
+        .to_string();
+    let resized = utils::run(
+        &["magick", "convert", &img_orig_path,
+          "-limit", "memory", "100MiB",
+          // "-limit", "map", "200MiB",
+          "-resize", &format!("{s}x{s}", s=bot_config::IMG_RESIZE_TARGET),
+          "-quality", &bot_config::IMG_RESIZE_QUALITY.to_string(),
+          &img_resized]);
+    // Only removes temp files, not the local file we are asked to
+    // send.
+    img_orig.remove();
+    resized?;
+    Ok(PhotoFile::Temp(img_resized))
+}
+
+/// Send the photo at `uri`, which is a URL or a local path, with
+/// `caption` in `format`.
+pub async fn sendPhoto(api: &bot::Api, uri: &str, caption: &str,
+                       format: bot_config::TextFormat, chat_id: i64)
+                       -> Result<Message, Error>
+{
+    debug!("Sending photo at {}...", uri);
+    let photo = preparePhoto(uri).await?;
+    let chat = bot::types::ChatId::new(chat_id);
+    let req = match &photo
+    {
+        PhotoFile::Uri(u) => SendPhoto::new(chat, bot::types::InputFileRef::new(
+            u.clone())),
+        PhotoFile::Local(f) | PhotoFile::Temp(f) => SendPhoto::new(
+            chat, bot::types::InputFileUpload::with_path(f.clone())),
+    };
+    let result = sendPhotoWithCaption(api, req, caption, format).await;
+    photo.remove();
+    result
+}
+
+/// Forward the message with `msg_id` from `from_chat` to `to_chat`.
+/// Return the ID of the forwarded message.
+pub async fn forwardMessage(api: &bot::Api, msg_id: bot::MessageId,
+                            from_chat: bot::ChatId, to_chat: bot::ChatId)
+                            -> Result<bot::MessageId, Error>
+{
+    let post = api.send(ForwardMessage::new(msg_id, from_chat, to_chat)).await
+        .map_err(|_| error!(RuntimeError, "Failed to forward message"))?;
+    Ok(post.id)
+}
+
+/// Call the Bot API method `method` with `params` directly, and
+/// return the result. This is for the methods or parameters that
+/// `telegram_bot` does not support.
+pub async fn callMethod(token: &str, method: &str, params: &serde_json::Value)
+                        -> Result<serde_json::Value, Error>
+{
+    let url = format!("https://api.telegram.org/bot{}/{}", token, method);
+    let res = reqwest::Client::new().post(&url).json(params).send().await
+        .map_err(|_| error!(RuntimeError, format!("Failed to call {}", method)))?;
+    methodResult(method, res).await
+}
+
+/// Call the Bot API method `method` with a multipart form, which
+/// can upload files.
+async fn callMethodMultipart(token: &str, method: &str,
+                             form: reqwest::multipart::Form)
+                             -> Result<serde_json::Value, Error>
+{
+    let url = format!("https://api.telegram.org/bot{}/{}", token, method);
+    let res = reqwest::Client::new().post(&url).multipart(form).send().await
+        .map_err(|_| error!(RuntimeError, format!("Failed to call {}", method)))?;
+    methodResult(method, res).await
+}
+
+async fn methodResult(method: &str, res: reqwest::Response)
+                      -> Result<serde_json::Value, Error>
+{
+    let mut data: serde_json::Value = res.json().await.map_err(
+        |_| error!(RuntimeError, format!("Invalid response from {}", method)))?;
+    if data["ok"].as_bool() != Some(true)
+    {
+        return Err(error!(RuntimeError, format!(
+            "{} failed: {}", method,
+            data["description"].as_str().unwrap_or("unknown error"))));
+    }
+    Ok(data["result"].take())
+}
+
+/// Return the ID of the pinned message in the chat, if there is one.
+pub async fn getPinnedMsgId(token: &str, chat_id: i64) -> Result<Option<i64>, Error>
+{
+    let chat = callMethod(token, "getChat", &json!({"chat_id": chat_id})).await?;
+    Ok(chat["pinned_message"]["message_id"].as_i64())
+}
+
+pub async fn pinMessage(token: &str, chat_id: i64, msg_id: i64) -> Result<(), Error>
+{
+    callMethod(token, "pinChatMessage", &json!({
+        "chat_id": chat_id,
+        "message_id": msg_id,
+        "disable_notification": true,
+    })).await.map(|_| ())
+}
+
+/// Send a poll that replies to `reply_to`. Return the ID of the
+/// message and the ID of the poll.
+pub async fn sendPoll(token: &str, chat_id: i64, question: &str,
+                      options: &[String], reply_to: Option<i64>)
+                      -> Result<(i64, String), Error>
+{
+    let mut params = json!({
+        "chat_id": chat_id,
+        "question": question,
+        "options": options,
+    });
+    if let Some(msg_id) = reply_to
+    {
+        params["reply_to_message_id"] = json!(msg_id);
+    }
+    let msg = callMethod(token, "sendPoll", &params).await?;
+    let msg_id = msg["message_id"].as_i64().ok_or_else(
+        || error!(RuntimeError, "Invalid message from sendPoll"))?;
+    let poll_id = msg["poll"]["id"].as_str().ok_or_else(
+        || error!(RuntimeError, "Invalid poll from sendPoll"))?;
+    Ok((msg_id, poll_id.to_owned()))
+}
+
+/// Stop the poll in the message. Return the number of votes of each
+/// option.
+pub async fn stopPoll(token: &str, chat_id: i64, msg_id: i64)
+                      -> Result<Vec<i64>, Error>
+{
+    let poll = callMethod(token, "stopPoll", &json!({
+        "chat_id": chat_id,
+        "message_id": msg_id,
+    })).await?;
+    let options = poll["options"].as_array().ok_or_else(
+        || error!(RuntimeError, "Invalid poll from stopPoll"))?;
+    Ok(options.iter().map(|o| o["voter_count"].as_i64().unwrap_or(0)).collect())
+}
+
+/// Send a copy of the message in `from_chat` to `chat_id`, with a new
+/// caption. Return the ID of the copy.
+pub async fn copyMessage(token: &str, chat_id: i64, from_chat: i64, msg_id: i64,
+                         caption: &str, format: bot_config::TextFormat)
+                         -> Result<i64, Error>
+{
+    let mut params = json!({
+        "chat_id": chat_id,
+        "from_chat_id": from_chat,
+        "message_id": msg_id,
+        "caption": caption,
+    });
+    if let Some(mode) = parseModeName(format)
+    {
+        params["parse_mode"] = json!(mode);
+    }
+    let result = callMethod(token, "copyMessage", &params).await?;
+    result["message_id"].as_i64().ok_or_else(
+        || error!(RuntimeError, "Invalid result from copyMessage"))
+}
+
+pub async fn unpinMessage(token: &str, chat_id: i64, msg_id: i64) -> Result<(), Error>
+{
+    callMethod(token, "unpinChatMessage", &json!({
+        "chat_id": chat_id,
+        "message_id": msg_id,
+    })).await.map(|_| ())
+}
+
+/// The full set of chat permissions, all set to `allowed`. Telegram
+/// takes a missing permission as false.
+fn allPermissions(allowed: bool) -> serde_json::Value
+{
+    json!({
+        "can_send_messages": allowed,
+        "can_send_media_messages": allowed,
+        "can_send_polls": allowed,
+        "can_send_other_messages": allowed,
+        "can_add_web_page_previews": allowed,
+        "can_change_info": allowed,
+        "can_invite_users": allowed,
+        "can_pin_messages": allowed,
+    })
+}
+
+/// Disallow the user to do anything in the chat, or give the user back
+/// the default permissions of the chat.
+pub async fn restrictMember(token: &str, chat_id: i64, user_id: i64,
+                            allowed: bool) -> Result<(), Error>
+{
+    let permissions = if allowed
+    {
+        let chat = callMethod(token, "getChat", &json!({"chat_id": chat_id}))
+            .await?;
+        match &chat["permissions"]
+        {
+            serde_json::Value::Null => allPermissions(true),
+            permissions => permissions.clone(),
+        }
+    }
+    else
+    {
+        allPermissions(false)
+    };
+    callMethod(token, "restrictChatMember", &json!({
+        "chat_id": chat_id,
+        "user_id": user_id,
+        "permissions": permissions,
+    })).await.map(|_| ())
+}
+
+/// Remove the user from the chat. The user can join again later.
+pub async fn kickMember(api: &bot::Api, chat_id: bot::ChatId, user_id: bot::UserId)
+                        -> Result<(), Error>
+{
+    api.send(KickChatMember::new(chat_id, user_id)).await.map_err(
+        |_| error!(RuntimeError, "Failed to kick chat member"))?;
+    api.send(UnbanChatMember::new(chat_id, user_id)).await.map_err(
+        |_| error!(RuntimeError, "Failed to unban chat member"))?;
+    Ok(())
+}
+
+pub async fn deleteMessage(api: &bot::Api, chat_id: bot::ChatId,
+                           msg_id: bot::MessageId) -> Result<(), Error>
+{
+    api.send(DeleteMessage::new(chat_id, msg_id)).await.map_err(
+        |_| error!(RuntimeError, "Failed to delete message"))?;
+    Ok(())
+}
+
+/// Read the file at `path` as a multipart part named `name`.
+fn filePart(path: &str, name: &str) -> Result<reqwest::multipart::Part, Error>
+{
+    let data = fs::read(path).map_err(
+        |_| error!(RuntimeError, format!("Failed to read {}", path)))?;
+    Ok(reqwest::multipart::Part::bytes(data).file_name(format!("{}.jpg", name)))
+}
+
+/// Send the prepared photos with `caption` on the first one. A single
+/// photo is sent with sendPhoto, because an album needs at least 2.
+async fn sendPreparedPhotos(token: &str, photos: &[PhotoFile], caption: &str,
+                            format: bot_config::TextFormat, chat_id: i64)
+                            -> Result<Vec<Message>, Error>
+{
+    let mut form = reqwest::multipart::Form::new()
+        .text("chat_id", chat_id.to_string());
+    if photos.len() == 1
+    {
+        form = match &photos[0]
+        {
+            PhotoFile::Uri(u) => form.text("photo", u.clone()),
+            PhotoFile::Local(f) | PhotoFile::Temp(f) =>
+                form.part("photo", filePart(f, "photo")?),
+        };
+        form = form.text("caption", caption.to_owned());
+        if let Some(mode) = parseModeName(format)
         {
-            f.to_string()
+            form = form.text("parse_mode", mode.to_owned());
         }
-        else
+        let msg = serde_json::from_value(
+            callMethodMultipart(token, "sendPhoto", form).await?).map_err(
+            |_| error!(RuntimeError, "Invalid message from sendPhoto"))?;
+        return Ok(vec![msg]);
+    }
+
+    let mut media: Vec<serde_json::Value> = Vec::new();
+    for photo in photos
+    {
+        let name = format!("photo{}", media.len());
+        let mut item = match photo
         {
-            let f = getTempFile(None)?.to_str().ok_or(
-                error!(RuntimeError, "Failed to encode temp file path"))?
-                .to_string();
-            downloadFile(uri, &f)?;
-            f
+            PhotoFile::Uri(u) => json!({"type": "photo", "media": u}),
+            PhotoFile::Local(f) | PhotoFile::Temp(f) =>
+            {
+                form = form.part(name.clone(), filePart(f, &name)?);
+                json!({"type": "photo", "media": format!("attach://{}", name)})
+            },
         };
-        // Resize
-        info!("Resizing image to {}...", bot_config::IMG_RESIZE_TARGET);
-        // Due to the limitation of the VPS, this is surprisingly easy to
-        // fail.
-        let img_resized = getTempFile(Some(".jpg"))?.to_str().ok_or(
-            error!(RuntimeError, "Failed to encode temp file path"))?
-            .to_string();
-        let img_orig_ref = &img_orig;
-        if let Err(e) = utils::run(
-            &["magick", "convert", img_orig_ref,
-              "-limit", "memory", "100MiB",
-              // "-limit", "map", "200MiB",
-              "-resize", &format!("{s}x{s}", s=bot_config::IMG_RESIZE_TARGET),
-              "-quality", &bot_config::IMG_RESIZE_QUALITY.to_string(),
-              &img_resized])
+        if media.is_empty()
+        {
+            item["caption"] = json!(caption);
+            if let Some(mode) = parseModeName(format)
+            {
+                item["parse_mode"] = json!(mode);
+            }
+        }
+        media.push(item);
+    }
+    form = form.text("media", serde_json::Value::from(media).to_string());
+    serde_json::from_value(callMethodMultipart(token, "sendMediaGroup", form).await?)
+        .map_err(|_| error!(RuntimeError, "Invalid messages from sendMediaGroup"))
+}
+
+/// Send the images at `uris` as an album, with `caption` on the first
+/// image. Telegram allows at most 10 images in an album, so the rest
+/// are dropped. Images that are too large are resized and uploaded,
+/// and images that fail to process are skipped. If only one image is
+/// left, it is sent as a single photo.
+pub async fn sendMediaGroup(token: &str, uris: &[String], caption: &str,
+                            format: bot_config::TextFormat, chat_id: i64)
+                            -> Result<Vec<Message>, Error>
+{
+    sendMediaGroupWith(token, uris, |_| Ok(caption.to_owned()), format, chat_id)
+        .await
+}
+
+/// Like `sendMediaGroup()`, but the caption is made by `makeCaption`
+/// from the indices in `uris` of the images that are left after the
+/// skipped ones. Nothing is sent if `makeCaption` fails.
+pub async fn sendMediaGroupWith<F>(token: &str, uris: &[String], makeCaption: F,
+                                   format: bot_config::TextFormat, chat_id: i64)
+                                   -> Result<Vec<Message>, Error>
+    where F: FnOnce(&[usize]) -> Result<String, Error>
+{
+    debug!("Sending album of {} images...", uris.len());
+    let mut photos: Vec<PhotoFile> = Vec::new();
+    let mut indices: Vec<usize> = Vec::new();
+    for (i, uri) in uris.iter().enumerate().take(10)
+    {
+        match preparePhoto(uri).await
         {
-            fs::remove_file(img_orig_ref).map_err(
-                |_| error!(RuntimeError,
-                           format!("Failed to remove temp file: {}", img_orig_ref)))?;
-            return Err(e);
+            Ok(photo) =>
+            {
+                photos.push(photo);
+                indices.push(i);
+            },
+            Err(e) => info!("Skipping {} in the album: {}", uri, e),
         }
-        fs::remove_file(img_orig_ref).map_err(
-            |_| error!(RuntimeError,
-                       format!("Failed to remove temp file: {}", img_orig_ref)))?;
-        api.send(SendPhoto::new(
-            bot::types::ChatId::new(chat_id),
-            bot::types::InputFileUpload::with_path(img_resized))
-                 .caption(caption)).await.map_err(
-            |_| error!(RuntimeError, "Failed to send photo"))
     }
+    if photos.is_empty()
+    {
+        return Err(error!(RuntimeError, "No image to send in the album"));
+    }
+
+    let result = match makeCaption(&indices)
+    {
+        Ok(caption) =>
+            sendPreparedPhotos(token, &photos, &caption, format, chat_id).await,
+        Err(e) => Err(e),
+    };
+    for photo in &photos
+    {
+        photo.remove();
+    }
+    result
+}
+
+/// Send the video or animation at `uri` with the Bot API method
+/// `method`, where `field` is the name of the file parameter.
+async fn sendMediaFromUri(token: &str, method: &str, field: &str, uri: &str,
+                          caption: &str, format: bot_config::TextFormat,
+                          chat_id: i64) -> Result<Message, Error>
+{
+    debug!("Sending {} at {}...", field, uri);
+    let file_info = getUriFileSize(uri).await?;
+    if let Some(f) = &file_info.filename
+    {
+        fs::remove_file(f).map_err(
+            |_| error!(RuntimeError, format!("Failed to remove temp file: {}", f)))?;
+    }
+    if file_info.size >= bot_config::TG_URI_FILE_SIZE_LIMIT
+    {
+        return Err(error!(RuntimeError, format!(
+            "{} at {} is too large ({} bytes)", field, uri, file_info.size)));
+    }
+
+    let mut params = json!({
+        "chat_id": chat_id,
+        field: uri,
+        "caption": caption,
+    });
+    if let Some(mode) = parseModeName(format)
+    {
+        params["parse_mode"] = json!(mode);
+    }
+    let result = callMethod(token, method, &params).await?;
+    serde_json::from_value(result).map_err(
+        |_| error!(RuntimeError, format!("Invalid message from {}", method)))
+}
+
+pub async fn sendVideo(token: &str, uri: &str, caption: &str,
+                       format: bot_config::TextFormat, chat_id: i64)
+                       -> Result<Message, Error>
+{
+    sendMediaFromUri(token, "sendVideo", "video", uri, caption, format, chat_id)
+        .await
+}
+
+pub async fn sendAnimation(token: &str, uri: &str, caption: &str,
+                           format: bot_config::TextFormat, chat_id: i64)
+                           -> Result<Message, Error>
+{
+    sendMediaFromUri(token, "sendAnimation", "animation", uri, caption, format,
+                     chat_id).await
 }
@@ -105,10 +105,25 @@ impl SimpleTemplate
                                           -> Self
     {
         let pattern = regex::Regex::new(&format!(r"\$\{{{}\}}", key)).unwrap();
-        Self::new(&pattern.replace_all(&self.tplt, &format!("{}", value)[..])
+        let value = format!("{}", value);
+        // Do not expand `$` in the value as a capture group.
+        Self::new(&pattern.replace_all(&self.tplt, regex::NoExpand(&value))
                   .into_owned())
     }
 
+    /// Substitute all the `values` in one pass, so that a value that
+    /// contains `${key}` is not expanded again. Unknown keys are kept.
+    pub fn applyAll(self, values: &[(&str, String)]) -> Self
+    {
+        let pattern = regex::Regex::new(r"\$\{(\w+)\}").unwrap();
+        let result = pattern.replace_all(&self.tplt, |caps: &regex::Captures| {
+            values.iter().find(|(key, _)| *key == &caps[1])
+                .map(|(_, value)| value.clone())
+                .unwrap_or_else(|| caps[0].to_owned())
+        }).into_owned();
+        Self::new(&result)
+    }
+
     pub fn result(self) -> String
     {
         self.tplt
@@ -121,6 +136,12 @@ fn testTemplate()
     let t = SimpleTemplate::new("${user}，你已经是一个键盘侠啦！快来和大家打个招呼吧~");
     assert_eq!(&t.apply("user", "abc").result(),
                "abc，你已经是一个键盘侠啦！快来和大家打个招呼吧~");
+    assert_eq!(&SimpleTemplate::new("Only ${price}").apply("price", "$1 ${x}")
+               .result(), "Only $1 ${x}");
+    let values = [("title", "${author}'s $1".to_owned()),
+                  ("author", "abc".to_owned())];
+    assert_eq!(&SimpleTemplate::new("${title} by ${author} ${x}").applyAll(&values)
+               .result(), "${author}'s $1 by abc ${x}");
 }
 
 pub fn run<I, S>(command: I) -> Result<(), Error>